@@ -0,0 +1,56 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use invaders::bus::Bus;
+use invaders::cpu::Cpu;
+
+const STEPS: u64 = 10_000;
+
+//a loop over video ram with a subroutine call, so there are reads,
+//writes the beam has to catch up with, stack traffic and branches
+const PROGRAM: [u8; 0x24] = [
+    0x31, 0x00, 0x24, //lxi sp,2400
+    0x21, 0x00, 0x24, //loop: lxi h,2400
+    0x0e, 0x40, //mvi c,40
+    0x7e, //inner: mov a,m
+    0x80, //add b
+    0x77, //mov m,a
+    0x23, //inx h
+    0xcd, 0x20, 0x00, //call 0020
+    0x0d, //dcr c
+    0xc2, 0x08, 0x00, //jnz inner
+    0x04, //inr b
+    0xc3, 0x03, 0x00, //jmp loop
+    0, 0, 0, 0, 0, 0, 0, 0, 0,
+    0x7c, //mov a,h
+    0xad, //xra l
+    0x5f, //mov e,a
+    0xc9, //ret
+];
+
+fn run(predecode: bool) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.bus.load_bin(0, &PROGRAM);
+    cpu.reset();
+    cpu.predecode = predecode;
+    cpu
+}
+
+fn cpu(c: &mut Criterion) {
+    let mut group = c.benchmark_group("8080");
+    //instructions per second, so a million of them is one MIPS
+    group.throughput(Throughput::Elements(STEPS));
+    for (name, predecode) in [("interpreter", false), ("predecoded", true)] {
+	let mut cpu = run(predecode);
+	group.bench_function(name, |b| {
+	    b.iter(|| {
+		for _ in 0..STEPS {
+		    let cyc = cpu.step();
+		    cpu.bus.step(cyc);
+		}
+	    })
+	});
+    }
+    group.finish();
+}
+
+criterion_group!(benches, cpu);
+criterion_main!(benches);
@@ -0,0 +1,37 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use invaders::bus::VRAM_DIRTY_WORDS;
+use invaders::video::{self, Overlay, Screen};
+use std::hint::black_box;
+
+//something like a busy attract screen
+fn test_vram() -> Vec<u8> {
+    (0..0x1c00).map(|i: usize| if (i / 32) % 16 < 8 { (i * 37) as u8 } else { 0 }).collect()
+}
+
+fn render(c: &mut Criterion) {
+    let vram = test_vram();
+    let colors = Overlay::Midway.color_map();
+
+    let mut frame = vec![0u32; video::WIDTH * video::HEIGHT];
+    c.bench_function("full rotation", |b| {
+	b.iter(|| video::render(black_box(&vram), &colors, &mut frame))
+    });
+
+    let mut screen = Screen::new(colors.clone());
+    let all = [!0u64; VRAM_DIRTY_WORDS];
+    c.bench_function("lut, all dirty", |b| {
+	b.iter(|| screen.update(black_box(&vram), &all))
+    });
+
+    //a frame of gameplay touches a few hundred bytes at most
+    let mut some = [0u64; VRAM_DIRTY_WORDS];
+    for i in (0..0x1c00).step_by(23) {
+	some[i / 64] |= 1 << (i % 64);
+    }
+    c.bench_function("lut, 4% dirty", |b| {
+	b.iter(|| screen.update(black_box(&vram), &some))
+    });
+}
+
+criterion_group!(benches, render);
+criterion_main!(benches);
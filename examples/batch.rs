@@ -0,0 +1,35 @@
+//plays a lot of games of Space Invaders at once with nobody at the
+//controls and reports how fast they went
+//    cargo run --release --example batch -- [machines] [frames] [rom path]
+use invaders::batch::Batch;
+use invaders::bus::Bus;
+use invaders::cpu::Cpu;
+use invaders::driver::{self, Input};
+use invaders::rom;
+use std::env;
+use std::time::Instant;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let n = args.get(1).map_or(64, |s| s.parse().expect("machines should be a number"));
+    let frames = args.get(2).map_or(600, |s| s.parse().expect("frames should be a number"));
+    let path = args.get(3).cloned().unwrap_or_else(|| rom::default_path("invaders"));
+
+    let mut cpu = Cpu::new();
+    let buf = rom::load(&path, driver::INVADERS.roms, true).unwrap_or_else(|e| panic!("{e}"));
+    cpu.bus.load_bin(0, &buf);
+    cpu.reset();
+
+    let mut batch = Batch::new(&cpu, n, 0);
+    let start = Instant::now();
+    //drop a coin in and start a game, each machine a frame later than the last
+    batch.run(frames, |i, cpu| {
+	let f = cpu.bus.frame.wrapping_sub(i);
+	cpu.bus.set_input(Input::Coin, f == 60);
+	cpu.bus.set_input(Input::P1Start, f == 120);
+    });
+    let secs = start.elapsed().as_secs_f64();
+    let total = (n * frames) as f64;
+    println!("{n} machines, {frames} frames each in {secs:.2}s");
+    println!("{:.0} frames/s, {:.1}x real time", total / secs, total / secs / 60.0);
+}
@@ -0,0 +1,35 @@
+//runs a CP/M .com program such as the ZEXDOC and ZEXALL exercisers.
+//only the two BDOS console calls they use are there (see
+//CpmBus::load_com), and it stops once the program jumps back to 0
+//    cargo run --release --example cpm -- zexdoc.com z80
+use invaders::bus::{Bus, CpmBus};
+use invaders::cpu::{Cpu, Model};
+use std::env;
+use std::fs;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+	eprintln!("usage: cpm <program.com> [8080|8085|z80]");
+	return;
+    }
+    let model = match args.get(2).map(|s| s.as_str()) {
+	None | Some("8080") => Model::I8080,
+	Some("8085") => Model::I8085,
+	Some("z80") => Model::Z80,
+	Some(m) => panic!("unknown cpu {m}"),
+    };
+    let prog = fs::read(&args[1]).unwrap_or_else(|e| panic!("couldn't read {}: {e}", args[1]));
+
+    let mut cpu = Cpu::with_bus(CpmBus::new(), model);
+    cpu.bus.load_com(&prog);
+    cpu.reset();
+    cpu.pc = 0x100;
+
+    while cpu.pc != 0 {
+	let cyc = cpu.step();
+	cpu.bus.step(cyc);
+    }
+    println!();
+    println!("{} cycles", cpu.cycles);
+}
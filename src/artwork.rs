@@ -0,0 +1,154 @@
+use crate::video::{WIDTH, HEIGHT, scale_rgb, add_rgb};
+use std::fs::File;
+use std::io::BufWriter;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Align {
+    Stretch,
+    Center,
+    Top,
+    Bottom,
+}
+
+impl Align {
+    pub fn from_name(name: &str) -> Option<Self> {
+	match name {
+	    "stretch" => Some(Align::Stretch),
+	    "center" => Some(Align::Center),
+	    "top" => Some(Align::Top),
+	    "bottom" => Some(Align::Bottom),
+	    _ => None,
+	}
+    }
+}
+
+//backdrop image resampled to screen size, the cabinet's moon picture
+//was lit independently of the monitor so lit pixels are added on top
+pub struct Backdrop {
+    px: Vec<u32>,
+}
+
+impl Backdrop {
+    pub fn load(path: &str, align: Align, offs: (i32, i32), brightness: f32) -> Result<Self, String> {
+	let (img_w, img_h, img) = load_png(path)?;
+	let mut px = vec![0u32; WIDTH * HEIGHT];
+	let (w, h) = match align {
+	    Align::Stretch => (WIDTH, HEIGHT),
+	    _ => (img_w, img_h),
+	};
+	let cx = (WIDTH as i32 - w as i32) / 2;
+	let (x0, y0) = match align {
+	    Align::Stretch => (0, 0),
+	    Align::Center => (cx, (HEIGHT as i32 - h as i32) / 2),
+	    Align::Top => (cx, 0),
+	    Align::Bottom => (cx, HEIGHT as i32 - h as i32),
+	};
+	let (x0, y0) = (x0 + offs.0, y0 + offs.1);
+
+	for y in 0..HEIGHT {
+	    for x in 0..WIDTH {
+		let (dx, dy) = (x as i32 - x0, y as i32 - y0);
+		if dx < 0 || dy < 0 || dx >= w as i32 || dy >= h as i32 {
+		    continue;
+		}
+		//nearest neighbour is plenty for a dim background
+		let sx = dx as usize * img_w / w;
+		let sy = dy as usize * img_h / h;
+		px[y * WIDTH + x] = scale_rgb(img[sy * img_w + sx], brightness);
+	    }
+	}
+	Ok(Backdrop { px })
+    }
+
+    pub fn composite(&self, frame: &mut [u32]) {
+	for (f, b) in frame.iter_mut().zip(self.px.iter()) {
+	    *f = add_rgb(*f, *b);
+	}
+    }
+}
+
+//returns width, height and 0x00RRGGBB pixels
+fn load_png(path: &str) -> Result<(usize, usize, Vec<u32>), String> {
+    let file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+    let mut decoder = png::Decoder::new(file);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| format!("{path}: {e}"))?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| format!("{path}: {e}"))?;
+    let (w, h) = (info.width as usize, info.height as usize);
+    let bpp = info.color_type.samples();
+    let mut px = Vec::with_capacity(w * h);
+    for y in 0..h {
+	let row = &buf[y * info.line_size..];
+	for x in 0..w {
+	    let s = &row[x * bpp..x * bpp + bpp];
+	    let (r, g, b) = match bpp {
+		1 | 2 => (s[0], s[0], s[0]),
+		_ => (s[0], s[1], s[2]),
+	    };
+	    //premultiply so transparent areas stay black
+	    let a = match bpp {
+		2 => s[1],
+		4 => s[3],
+		_ => 0xff,
+	    } as u32;
+	    let c = ((r as u32) << 16) | ((g as u32) << 8) | b as u32;
+	    px.push(scale_rgb(c, a as f32 / 255.0));
+	}
+    }
+    Ok((w, h, px))
+}
+
+pub fn save_png(path: &str, frame: &[u32], w: usize, h: usize) -> Result<(), String> {
+    let file = File::create(path).map_err(|e| format!("{path}: {e}"))?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), w as u32, h as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| format!("{path}: {e}"))?;
+    let data: Vec<u8> = frame.iter()
+	.flat_map(|c| [(c >> 16) as u8, (c >> 8) as u8, *c as u8])
+	.collect();
+    writer.write_image_data(&data).map_err(|e| format!("{path}: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::{Bus, InvBus, CYCLES_PER_FRAME};
+    use crate::driver::INVADPT2;
+    use crate::video::{Screen, cell_color_map};
+
+    //part II screen drawn headless the way a screenshot is, from a made up
+    //colour prom and a pattern written through the bus, against a picture
+    //checked in next to the sources. a mismatch leaves what was drawn in
+    //the temp directory to look at
+    #[test]
+    fn part2_screenshot() {
+	let mut bus = InvBus::with_driver(&INVADPT2);
+	let proms: Vec<u8> = (0..0x800).map(|i| ((i >> 5) + i) as u8 % 7 + 1).collect();
+	bus.load_proms(&proms);
+	for i in 0..0x1c00 {
+	    let (x, y) = (i / 32, i % 32);
+	    let byte = match (x / 8 + y) % 4 {
+		0 => 0xff,
+		1 => 0x81,
+		2 => 1 << (x % 8),
+		_ => 0,
+	    };
+	    bus.write_byte(0x2400 + i as u16, byte);
+	}
+	bus.step(CYCLES_PER_FRAME);
+
+	let mut screen = Screen::new(vec![0; WIDTH * HEIGHT]);
+	screen.set_colors(cell_color_map(&bus.take_cell_colors().unwrap()));
+	let dirty = bus.take_display_dirty();
+	screen.update(&bus.display, &dirty);
+	let reference = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/part2.png");
+	if load_png(reference).ok() != Some((WIDTH, HEIGHT, screen.px.clone())) {
+	    let out = std::env::temp_dir().join(format!("invaders-part2-{}.png", std::process::id()));
+	    let out = out.to_str().unwrap();
+	    save_png(out, &screen.px, WIDTH, HEIGHT).unwrap();
+	    panic!("drawn screen differs from {reference}, see {out}");
+	}
+    }
+}
@@ -0,0 +1,56 @@
+//runs lots of machines at once, for bots and other experiments that
+//want many games played headless. they're spread over rayon's pool
+use crate::bus::Bus;
+use crate::cpu::Cpu;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+
+//run a machine up to the start of its next vertical blank
+pub fn run_frame(cpu: &mut Cpu) {
+    loop {
+	let cyc = cpu.step();
+	cpu.bus.step(cyc);
+	if cpu.bus.vblank {
+	    cpu.bus.vblank = false;
+	    return;
+	}
+    }
+}
+
+pub struct Batch {
+    pub machines: Vec<Cpu>,
+    pool: ThreadPool,
+}
+
+impl Batch {
+    //n copies of a machine that's already loaded and set up, sharing its
+    //roms. threads of 0 means one per core
+    pub fn new(proto: &Cpu, n: usize, threads: usize) -> Self {
+	let pool = ThreadPoolBuilder::new()
+	    .num_threads(threads)
+	    .build()
+	    .unwrap_or_else(|e| panic!("couldn't start the batch threads: {e}"));
+	Batch {
+	    machines: vec![proto.clone(); n],
+	    pool,
+	}
+    }
+
+    //run every machine for the given number of frames. control is called
+    //with each machine's index before each of its frames, to set inputs
+    //or look at the state, and has to be safe to call from any thread
+    pub fn run<F>(&mut self, frames: usize, control: F)
+    where
+	F: Fn(usize, &mut Cpu) + Sync,
+    {
+	let machines = &mut self.machines;
+	self.pool.install(|| {
+	    machines.par_iter_mut().enumerate().for_each(|(i, cpu)| {
+		for _ in 0..frames {
+		    control(i, cpu);
+		    run_frame(cpu);
+		}
+	    });
+	});
+    }
+}
@@ -1,11 +1,40 @@
-const ROM_START: u16 = 0x0000;
-const ROM_END: u16 = 0x1fff;
-const RAM_START: u16 = 0x2000;
-const RAM_END: u16 = 0x23ff;
-const VRAM_START: u16 = 0x2400;
-const VRAM_END: u16 = 0x3fff;
-
-pub trait Bus {
+use crate::driver::{self, Driver, Input, Mem, PortRead, PortWrite, Region, Source};
+use crate::mb14241::Mb14241;
+use std::fmt;
+use std::sync::Arc;
+
+const OPEN_BUS: u8 = 0xff; //nothing drives the data bus, so it floats high
+pub const VRAM_DIRTY_WORDS: usize = 0x1c00 / 64;
+
+//the monitor is on its side, so each 32 byte row of vram is one scanline
+pub const CYCLES_PER_FRAME: usize = 2_000_000 / 60;
+pub const LINES_PER_FRAME: usize = 262;
+pub const VISIBLE_LINES: usize = 224;
+const MID_LINE: usize = 96; //where the half frame interrupt fires
+
+//the kinds of machine cycle the 8080 puts on the bus
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cycle {
+    Fetch, //M1, reading an opcode
+    Read,
+    Write,
+    StackRead,
+    StackWrite,
+    IoRead,
+    IoWrite,
+    Inta,
+}
+
+//whatever answers the cpu's interrupt acknowledge. instead of reading
+//memory the cpu takes the instruction from here a byte per INTA cycle,
+//usually a one byte RST but it can be anything, even a 3 byte CALL
+pub trait InterruptController {
+    fn irq_pending(&self) -> bool;
+    //byte n of the instruction, 0 is the opcode and also accepts the request
+    fn inta(&mut self, n: u16) -> u8;
+}
+
+pub trait Bus: InterruptController {
     fn read_byte(&mut self, addr: u16) -> u8;
     fn read_word(&mut self, addr: u16) -> u16;
     fn write_byte(&mut self, addr: u16, data: u8);
@@ -14,37 +43,115 @@ pub trait Bus {
     fn write_io_byte(&mut self, port: u8, data: u8);
     fn load_bin(&mut self, offs: usize, buf: &[u8]);
     fn step(&mut self, cyc: usize);
+    //told as each machine cycle starts, t states into the instruction.
+    //returns how many wait states the access holds the cpu for
+    fn cycle(&mut self, _kind: Cycle, _addr: u16, _t: usize) -> usize {
+	0
+    }
+    //what the reset line does to the rest of the board
+    fn reset(&mut self) {
+    }
+    fn power_on(&mut self) {
+    }
+    //true once when the board pulls the reset line itself
+    fn take_reset(&mut self) -> bool {
+	false
+    }
+    //where the code at addr is really stored, for the cpu to cache it
+    //decoded. None if it can't be, because reading there does something
+    //or cycle() does more than return the wait states
+    fn code_addr(&self, _addr: u16) -> Option<u16> {
+	None
+    }
 }
 
+//ports nothing answers on, so each one is only reported the first time
+#[derive(Clone)]
+struct PortLog([u64; 8]);
+
+impl PortLog {
+    fn first(&mut self, write: bool, port: u8) -> bool {
+	let n = (write as usize) << 8 | port as usize;
+	let new = self.0[n / 64] & (1 << (n % 64)) == 0;
+	self.0[n / 64] |= 1 << (n % 64);
+	new
+    }
+}
+
+#[derive(Clone)]
 pub struct InvBus {
-    rom: [u8; 0x2000],
+    pub driver: &'static Driver,
+    rom: Arc<Vec<u8>>, //shared between clones until one loads over it
     ram: [u8; 0x400],
     pub vram: [u8; 0x1c00],
+    vram_dirty: [u64; VRAM_DIRTY_WORDS],
+    pub display: [u8; 0x1c00],
+    display_dirty: [u64; VRAM_DIRTY_WORDS],
     cycles: usize,
-    shift_amt: u8,
-    watchdog: u8,
-    shift_reg: u16,
+    t_done: usize, //how far into the current instruction the beam has been run
+    line: usize,
+    watchdog: usize, //frames since the game last kicked it
+    pub watchdog_frames: usize, //0 for no watchdog
+    pub reset_pending: bool,
+    pub frame: usize,
+    shifter: Mb14241,
     pub irq: bool,
     pub irq_vec: u8,
-    pub credit: bool,
-    pub p1_start: bool,
-    pub p2_start: bool,
-    pub p1_fire: bool,
-    pub p2_fire: bool,
-    pub p1_left: bool,
-    pub p2_left: bool,
-    pub p1_right: bool,
-    pub p2_right: bool,
+    inputs: u32,
     pub dip: u8,
-    half: bool,
     pub vblank: bool,
-    pub sfx: [bool; 10],
+    pub sfx: [bool; 16],
+    proms: Arc<Vec<u8>>,
+    color_bank: bool,
+    screen_red: bool,
+    colors_changed: bool,
+    pub log_unmapped: bool,
+    pub open_bus: u8,
+    port_log: PortLog,
+    pub verbose: bool, //keep messages for take_log, off so batch runs stay quiet
+    log: Vec<String>,
 }
 
+//8080 code, so it runs the same in all three cpu modes. C=2 prints E,
+//C=9 prints the string at DE up to a '$'. port AA is the console
+const BDOS: u16 = 0xf000;
+const BDOS_STUB: [u8; 26] = [
+    0x79, //mov a,c
+    0xfe, 0x02, //cpi 2
+    0xca, 0x0c, 0xf0, //jz putc
+    0xfe, 0x09, //cpi 9
+    0xca, 0x10, 0xf0, //jz puts
+    0xc9, //ret
+    0x7b, //putc: mov a,e
+    0xd3, 0xaa, //out aa
+    0xc9, //ret
+    0x1a, //puts: ldax d
+    0xfe, 0x24, //cpi '$'
+    0xc8, //rz
+    0xd3, 0xaa, //out aa
+    0x13, //inx d
+    0xc3, 0x10, 0xf0, //jmp puts
+];
+
 pub struct CpmBus {
     ram: [u8; 0x10000],
     pub irq: bool,
     pub irq_vec: u8,
+    port_log: PortLog,
+    pub console: String, //everything printed so far
+}
+
+impl InterruptController for CpmBus {
+    fn irq_pending(&self) -> bool {
+	self.irq
+    }
+
+    fn inta(&mut self, n: u16) -> u8 {
+	if n == 0 {
+	    self.irq = false;
+	}
+	self.irq_vec
+    }
 }
 
 impl Bus for CpmBus {
@@ -67,20 +174,24 @@ impl Bus for CpmBus {
     }
 
     fn read_io_byte(&mut self, port: u8) -> u8 {
-	match port {
-	    _ =>
-		todo!("unhandled io port read {port:02x}"),
+	if self.port_log.first(false, port) {
+	    println!("read from unhandled io port {port:02X}");
 	}
+	OPEN_BUS
     }
 
     fn write_io_byte(&mut self, port: u8, data: u8) {
 	match port {
 	    0xaa => {
 		print!("{}", data as char);
+		self.console.push(data as char);
 	    },
 	    0xff => panic!("warm booted"),
-	    _ =>
-		todo!("unhandled io port write {port:02x}"),
+	    _ => {
+		if self.port_log.first(true, port) {
+		    println!("write of {data:02X} to unhandled io port {port:02X}");
+		}
+	    },
 	};
     }
 
@@ -92,19 +203,41 @@ impl Bus for CpmBus {
 
     fn step(&mut self, cyc: usize) {
     }
+
+    fn code_addr(&self, addr: u16) -> Option<u16> {
+	Some(addr)
+    }
+}
+
+//the midway board jams an RST onto the data bus with resistors
+impl InterruptController for InvBus {
+    fn irq_pending(&self) -> bool {
+	self.irq
+    }
+
+    fn inta(&mut self, n: u16) -> u8 {
+	if n == 0 {
+	    self.irq = false;
+	}
+	self.irq_vec
+    }
 }
 
 impl Bus for InvBus {
     fn read_byte(&mut self, addr: u16) -> u8 {
-	match addr {
-	    ROM_START ..= ROM_END =>
-		self.rom[addr as usize],
-	    RAM_START ..= RAM_END =>
-		self.ram[(addr - RAM_START) as usize],
-	    VRAM_START ..= VRAM_END =>
-		self.vram[(addr - VRAM_START) as usize],
-	    _ =>
-		self.ram[(addr % (RAM_END - RAM_START)) as usize],
+	match self.decode(addr) {
+	    Some((Mem::Rom, i)) =>
+		self.rom.get(i).copied().unwrap_or(0),
+	    Some((Mem::Ram, i)) =>
+		self.ram[i],
+	    Some((Mem::Vram, i)) =>
+		self.vram[i],
+	    _ => {
+		if self.log_unmapped {
+		    self.note(format_args!("read from unmapped address {addr:04X}"));
+		}
+		self.open_bus
+	    },
 	}
     }
 
@@ -114,15 +247,20 @@ impl Bus for InvBus {
     }
 
     fn write_byte(&mut self, addr: u16, data: u8) {
-	match addr {
-	    ROM_START ..= ROM_END =>
-		println!("attempted write to rom at {addr:04X}"),
-	    RAM_START ..= RAM_END =>
-		self.ram[(addr - RAM_START) as usize] = data,
-	    VRAM_START ..= VRAM_END =>
-		self.vram[(addr - VRAM_START) as usize] = data,
-	    _ =>
-		self.ram[(addr % (RAM_END - RAM_START)) as usize] = data,
+	match self.decode(addr) {
+	    Some((Mem::Rom, _)) =>
+		self.note(format_args!("attempted write to rom at {addr:04X}")),
+	    Some((Mem::Ram, i)) =>
+		self.ram[i] = data,
+	    Some((Mem::Vram, i)) => {
+		self.vram[i] = data;
+		self.vram_dirty[i / 64] |= 1 << (i % 64);
+	    },
+	    _ => {
+		if self.log_unmapped {
+		    self.note(format_args!("write of {data:02X} to unmapped address {addr:04X}"));
+		}
+	    },
 	};
     }
 
@@ -132,117 +270,322 @@ impl Bus for InvBus {
     }
 
     fn read_io_byte(&mut self, port: u8) -> u8 {
-	match port {
-	    0 => ((self.dip >> 4) & 1) | 0b01110000,
-	    1 => {
-		((self.p1_right as u8) << 6) | ((self.p1_left as u8) << 5) |
-		((self.p1_fire as u8) << 4) | 8 | ((self.p1_start as u8) << 2) |
-		((self.p2_start as u8) << 1) | self.credit as u8
+	let dev = self.driver.reads.iter().find(|(p, _)| *p == port).map(|(_, d)| *d);
+	match dev {
+	    Some(PortRead::Inputs(n)) => self.read_inputs(n),
+	    Some(PortRead::Shifter) => self.shifter.read_result(),
+	    Some(PortRead::ShifterReversed) => self.shifter.read_reversed(),
+	    Some(PortRead::Watchdog) => {
+		self.watchdog = 0;
+		0
 	    },
-	    2 => {
-		(self.dip & 0x80) | ((self.p2_right as u8) << 6) |
-		((self.p2_left as u8) << 5) | ((self.p2_fire as u8) << 4) |
-		(((self.dip >> 6) & 1) << 3) | (((self.dip >> 5) & 1) << 1) |
-		((self.dip >> 3) & 1)
+	    None => {
+		if self.port_log.first(false, port) {
+		    self.note(format_args!("read from unhandled io port {port:02X}"));
+		}
+		self.open_bus
 	    },
-	    3 => ((self.shift_reg << self.shift_amt) >> 8) as u8,
-	    6 => 0, //watchdog timer
-	    _ =>
-		todo!("unhandled io port read {port:02x}"),
 	}
     }
 
     fn write_io_byte(&mut self, port: u8, data: u8) {
-	match port {
-	    2 => self.shift_amt = data & 7,
-	    3 => {
-		let bits = data & 0xf;
-		for i in 0..4 {
-		    self.sfx[i] = if (bits >> i) & 1 != 0 {
-			println!("playing sfx {}", i);
-			true
-		    } else {
-			false
-		    };
-		}
-	    },
-	    4 => {
-		let tmp = (self.shift_reg >> 8) & 0xff;
-		self.shift_reg = (data as u16) << 8 | tmp;
-	    },
-	    5 => {
-		let bits = data & 0x1f;
-		for i in 0..5 {
-		    self.sfx[i + 4] = if (bits >> i) & 1 != 0 {
-			println!("playing sfx {}", i + 4);
+	let dev = self.driver.writes.iter().find(|(p, _)| *p == port).map(|(_, d)| *d);
+	match dev {
+	    Some(PortWrite::ShiftAmount) => self.shifter.write_amount(data),
+	    Some(PortWrite::ShiftData) => self.shifter.write_data(data),
+	    Some(PortWrite::Sound(first, count)) => {
+		for i in 0..count {
+		    self.sfx[first + i] = if (data >> i) & 1 != 0 {
+			self.note(format_args!("playing sfx {}", first + i));
 			true
 		    } else {
 			false
 		    };
 		}
 	    },
-	    6 => {}, //normally a watchdog access resets a timer, that if allowed to count down
+	    Some(PortWrite::Watchdog) => self.watchdog = 0, //a watchdog access resets a timer, that if allowed to count down
 	    //would reset the hardware. this probably only happens from hardware failure
 	    //in the case of the real machine, or improper emulation/corrupt rom dump,
-	    //so it isnt necessary to emulate accurately
-	    _ =>
-		todo!("unhandled io port write {port:02x}"),
+	    //so it's off unless asked for
+	    Some(PortWrite::Ignore) => {},
+	    None => {
+		if self.port_log.first(true, port) {
+		    self.note(format_args!("write of {data:02X} to unhandled io port {port:02X}"));
+		}
+	    },
 	};
+
+	if let Some(c) = self.driver.color {
+	    let old = (self.color_bank, self.screen_red);
+	    if port == c.bank.0 {
+		self.color_bank = (data >> c.bank.1) & 1 != 0;
+	    }
+	    if port == c.red.0 {
+		self.screen_red = (data >> c.red.1) & 1 != 0;
+	    }
+	    self.colors_changed |= old != (self.color_bank, self.screen_red);
+	}
     }
 
     fn load_bin(&mut self, offs: usize, buf: &[u8]) {
+	let rom = Arc::make_mut(&mut self.rom);
+	if rom.len() < offs + buf.len() {
+	    rom.resize(offs + buf.len(), 0);
+	}
 	for i in 0..buf.len() {
-	    self.rom[offs + i] = buf[i];
+	    rom[offs + i] = buf[i];
 	}
     }
 
     fn step(&mut self, cyc: usize) {
-	//count the time until interrupts
-	self.cycles += cyc;
-	if self.cycles >= 16667 / 2 && self.half { //half frame
-	    self.irq = true;
-	    self.irq_vec = 0xcf; //RST 8
-	    self.cycles -= 16667 / 2;
-	    self.half = false;
-	}
-	
-	if self.cycles >= 16667 { //~1 frame
-	    self.irq = true;
-	    self.irq_vec = 0xd7; //RST 10
-	    self.cycles -= 16667;
-	    self.half = true;
-	    self.vblank = true;
+	self.run_to(cyc);
+	self.t_done = 0;
+    }
+
+    fn cycle(&mut self, kind: Cycle, addr: u16, t: usize) -> usize {
+	let region = match kind {
+	    Cycle::IoRead | Cycle::IoWrite | Cycle::Inta => return 0,
+	    _ => self.region(addr),
+	};
+	//bring the beam up to the moment of a video write, so a line
+	//passing over it beforehand still shows the old byte
+	let write = kind == Cycle::Write || kind == Cycle::StackWrite;
+	if write && region.map(|r| r.mem) == Some(Mem::Vram) {
+	    self.run_to(t);
 	}
+	region.map_or(0, |r| r.wait)
+    }
+
+    //what the reset line clears, memory is left alone
+    fn reset(&mut self) {
+	self.shifter = Mb14241::new();
+	self.watchdog = 0;
+	self.reset_pending = false;
+	self.irq = false;
+	self.sfx = [false; 16];
+	self.color_bank = false;
+	self.screen_red = false;
+	self.colors_changed = true;
+    }
+
+    //like switching the machine off and on, the roms and switches stay
+    fn power_on(&mut self) {
+	self.reset();
+	self.ram = [0; 0x400];
+	self.vram = [0; 0x1c00];
+	self.vram_dirty = [!0; VRAM_DIRTY_WORDS];
+    }
+
+    fn take_reset(&mut self) -> bool {
+	std::mem::take(&mut self.reset_pending)
+    }
+
+    fn code_addr(&self, addr: u16) -> Option<u16> {
+	self.resolve(addr).map(|(_, a)| a)
     }
 }
 
 impl InvBus {
     pub fn new() -> Self {
+	InvBus::with_driver(&driver::INVADERS)
+    }
+
+    pub fn with_driver(driver: &'static Driver) -> Self {
 	InvBus {
-	    rom: [0; 0x2000],
+	    driver,
+	    rom: Arc::new(vec![0; 0x2000]),
 	    ram: [0; 0x400],
 	    vram: [0; 0x1c00],
+	    vram_dirty: [0; VRAM_DIRTY_WORDS],
+	    display: [0; 0x1c00],
+	    display_dirty: [!0; VRAM_DIRTY_WORDS], //draw everything the first time
 	    cycles: 0,
-	    shift_amt: 0,
+	    t_done: 0,
+	    line: 0,
 	    watchdog: 0,
-	    shift_reg: 0,
+	    watchdog_frames: 0,
+	    reset_pending: false,
+	    frame: 0,
+	    shifter: Mb14241::new(),
 	    irq: false,
 	    irq_vec: 0,
-	    credit: false,
-	    p1_start: false,
-	    p2_start: false,
-	    p1_fire: false,
-	    p2_fire: false,
-	    p1_left: false,
-	    p2_left: false,
-	    p1_right: false,
-	    p2_right: false,
-	    dip: 0,
-	    half: true,
+	    inputs: 0,
+	    dip: driver.dip_default,
 	    vblank: false,
-	    sfx: [false; 10],
+	    sfx: [false; 16],
+	    proms: Arc::new(Vec::new()),
+	    color_bank: false,
+	    screen_red: false,
+	    colors_changed: false,
+	    log_unmapped: false,
+	    open_bus: OPEN_BUS,
+	    port_log: PortLog([0; 8]),
+	    verbose: false,
+	    log: Vec::new(),
 	}
     }
+
+    //read memory without it counting as an access, for looking at the
+    //game's state from outside
+    pub fn peek(&self, addr: u16) -> u8 {
+	match self.decode(addr) {
+	    Some((Mem::Rom, i)) => self.rom.get(i).copied().unwrap_or(0),
+	    Some((Mem::Ram, i)) => self.ram[i],
+	    Some((Mem::Vram, i)) => self.vram[i],
+	    _ => self.open_bus,
+	}
+    }
+
+    //what the board had to say since last time, if verbose is on
+    pub fn take_log(&mut self) -> Vec<String> {
+	std::mem::take(&mut self.log)
+    }
+
+    fn note(&mut self, msg: fmt::Arguments) {
+	if self.verbose {
+	    self.log.push(msg.to_string());
+	}
+    }
+
+    //which memory an address lands in and the index into it
+    fn decode(&self, addr: u16) -> Option<(Mem, usize)> {
+	let (r, addr) = self.resolve(addr)?;
+	match r.mem {
+	    Mem::Rom => Some((Mem::Rom, addr as usize)),
+	    m => Some((m, (addr - r.start) as usize)),
+	}
+    }
+
+    fn region(&self, addr: u16) -> Option<&'static Region> {
+	self.resolve(addr).map(|(r, _)| r)
+    }
+
+    //follow mirrors down to the region that really answers and the address in it
+    fn resolve(&self, addr: u16) -> Option<(&'static Region, u16)> {
+	let mut addr = addr & self.driver.addr_mask;
+	loop {
+	    let r = self.driver.map.iter().find(|r| (r.start..=r.end).contains(&addr))?;
+	    match r.mem {
+		Mem::Mirror(dist) => addr -= dist,
+		_ => return Some((r, addr)),
+	    }
+	}
+    }
+
+    //move the beam to t states into the current instruction. the cpu
+    //never reports less for a whole instruction than it got to in a
+    //cycle, and the beam can't go backwards if a caller does
+    fn run_to(&mut self, t: usize) {
+	debug_assert!(t >= self.t_done, "beam asked back from {} to {t}", self.t_done);
+	self.cycles += t.saturating_sub(self.t_done);
+	self.t_done = self.t_done.max(t);
+	while self.cycles >= (self.line + 1) * CYCLES_PER_FRAME / LINES_PER_FRAME {
+	    self.end_line();
+	}
+    }
+
+    pub fn load_proms(&mut self, buf: &[u8]) {
+	self.proms = Arc::new(buf.to_vec());
+	self.colors_changed = true;
+    }
+
+    //the colour of each 8x8 cell if it changed since the last call,
+    //3 bits each in RBG order. None on monochrome boards
+    pub fn take_cell_colors(&mut self) -> Option<Vec<u8>> {
+	if !self.colors_changed || self.proms.len() < 0x800 {
+	    return None;
+	}
+	self.colors_changed = false;
+	if self.screen_red {
+	    return Some(vec![1; 0x400]);
+	}
+	let base = if self.color_bank { 0x400 } else { 0 };
+	Some(self.proms[base..base + 0x400].iter().map(|c| c & 7).collect())
+    }
+
+    pub fn set_input(&mut self, input: Input, down: bool) {
+	let bit = 1 << input as u32;
+	if down {
+	    self.inputs |= bit;
+	} else {
+	    self.inputs &= !bit;
+	}
+    }
+
+    fn read_inputs(&self, n: usize) -> u8 {
+	let port = &self.driver.inputs[n];
+	let mut val = 0;
+	for (bit, src) in port.bits {
+	    let on = match src {
+		Source::Input(i) => self.inputs & (1 << *i as u32) != 0,
+		Source::Dip(d) => (self.dip >> d) & 1 != 0,
+		Source::High => true,
+	    };
+	    val |= (on as u8) << bit;
+	}
+	val ^ port.active_low
+    }
+
+    pub fn beam_line(&self) -> usize {
+	self.line
+    }
+
+    //the beam has finished a line, show what was in vram by then
+    fn end_line(&mut self) {
+	if self.line < VISIBLE_LINES {
+	    self.latch_line(self.line);
+	}
+	self.line += 1;
+	match self.line {
+	    MID_LINE => {
+		self.irq = true;
+		self.irq_vec = 0xcf; //RST 8
+	    },
+	    VISIBLE_LINES => {
+		self.irq = true;
+		self.irq_vec = 0xd7; //RST 10
+		self.vblank = true;
+		self.frame += 1;
+		if self.watchdog_frames != 0 {
+		    self.watchdog += 1;
+		    if self.watchdog >= self.watchdog_frames {
+			let frame = self.frame;
+			self.note(format_args!("watchdog expired on frame {frame}, resetting"));
+			self.watchdog = 0;
+			self.reset_pending = true;
+		    }
+		}
+	    },
+	    LINES_PER_FRAME => {
+		self.line = 0;
+		self.cycles -= CYCLES_PER_FRAME;
+	    },
+	    _ => {},
+	}
+    }
+
+    fn latch_line(&mut self, line: usize) {
+	//a line is 32 bytes, half a dirty word
+	let (w, shift) = (line / 2, (line % 2) * 32);
+	let bits = (self.vram_dirty[w] >> shift) & 0xffff_ffff;
+	if bits != 0 {
+	    let offs = line * 32;
+	    self.display[offs..offs + 32].copy_from_slice(&self.vram[offs..offs + 32]);
+	    self.vram_dirty[w] &= !(0xffff_ffff << shift);
+	    self.display_dirty[w] |= bits << shift;
+	}
+    }
+
+    //which display bytes changed since the last call, one bit per byte
+    pub fn take_display_dirty(&mut self) -> [u64; VRAM_DIRTY_WORDS] {
+	std::mem::replace(&mut self.display_dirty, [0; VRAM_DIRTY_WORDS])
+    }
+}
+
+impl Default for InvBus {
+    fn default() -> Self {
+	InvBus::new()
+    }
 }
 
 impl CpmBus {
@@ -251,6 +594,138 @@ impl CpmBus {
 	    ram: [0; 0x10000],
 	    irq: false,
 	    irq_vec: 0,
+	    port_log: PortLog([0; 8]),
+	    console: String::new(),
+	}
+    }
+
+    //a .com program at 100 with just enough BDOS behind the call at 5 for
+    //the console calls test programs like ZEXDOC use. the cpu starts it
+    //from pc 100 and it's finished when it jumps back to 0
+    pub fn load_com(&mut self, prog: &[u8]) {
+	self.load_bin(0x100, prog);
+	self.load_bin(5, &[0xc3, BDOS as u8, (BDOS >> 8) as u8]);
+	self.load_bin(BDOS as usize, &BDOS_STUB);
+    }
+}
+
+impl Default for CpmBus {
+    fn default() -> Self {
+	CpmBus::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::driver::{INVADERS, INVADPT2};
+
+    //rom and ram with a hole between them and all 16 address lines decoded
+    const GAP_MAP: [Region; 2] = [
+	Region { start: 0x0000, end: 0x1fff, mem: Mem::Rom, wait: 0 },
+	Region { start: 0x2000, end: 0x23ff, mem: Mem::Ram, wait: 0 },
+    ];
+    const GAPPY: Driver = Driver { map: &GAP_MAP, addr_mask: 0xffff, ..INVADERS };
+
+    fn with_rom(driver: &'static Driver, len: usize) -> InvBus {
+	let mut bus = InvBus::with_driver(driver);
+	let rom: Vec<u8> = (0..len).map(|i| (i ^ i >> 8) as u8).collect();
+	bus.load_bin(0, &rom);
+	bus
+    }
+
+    #[test]
+    fn midway_regions() {
+	let mut bus = with_rom(&INVADERS, 0x2000);
+	assert_eq!(bus.read_byte(0x0123), 0x22);
+	assert_eq!(bus.read_byte(0x1fff), 0xe0);
+	bus.write_byte(0x2000, 0x11);
+	bus.write_byte(0x23ff, 0x22);
+	assert_eq!((bus.read_byte(0x2000), bus.read_byte(0x23ff)), (0x11, 0x22));
+	bus.write_byte(0x2400, 0x33);
+	bus.write_byte(0x3fff, 0x44);
+	assert_eq!((bus.vram[0], bus.vram[0x1bff]), (0x33, 0x44));
+	assert_eq!((bus.read_byte(0x2400), bus.read_byte(0x3fff)), (0x33, 0x44));
+	//only 14 address lines, so everything repeats every 16K
+	for base in [0x4000, 0x8000, 0xc000] {
+	    assert_eq!(bus.read_byte(base + 0x0123), 0x22);
+	    assert_eq!(bus.read_byte(base + 0x2000), 0x11);
+	    assert_eq!(bus.read_byte(base + 0x3fff), 0x44);
 	}
+	bus.write_byte(0x6001, 0x55);
+	bus.write_byte(0xe401, 0x66);
+	assert_eq!((bus.read_byte(0x2001), bus.vram[1]), (0x55, 0x66));
+    }
+
+    #[test]
+    fn taito_regions() {
+	let mut bus = with_rom(&INVADPT2, 0x6000);
+	assert_eq!(bus.read_byte(0x0123), 0x22);
+	assert_eq!(bus.read_byte(0x4123), 0x62);
+	bus.write_byte(0x2010, 0x77);
+	bus.write_byte(0x3000, 0x88);
+	//the top 8K repeats the 8K below it
+	assert_eq!(bus.read_byte(0x6010), 0x77);
+	assert_eq!(bus.read_byte(0x7000), 0x88);
+	bus.write_byte(0x6011, 0x99);
+	assert_eq!(bus.read_byte(0x2011), 0x99);
+	//and 15 lines are decoded
+	assert_eq!(bus.read_byte(0x8000 + 0x4123), 0x62);
+	assert_eq!(bus.read_byte(0x8000 + 0x6010), 0x77);
+    }
+
+    #[test]
+    fn rom_writes_ignored() {
+	for (driver, addr) in [(&INVADERS, 0x1f00), (&INVADPT2, 0x4123)] {
+	    let mut bus = with_rom(driver, 0x6000);
+	    bus.verbose = true;
+	    let old = [bus.read_byte(0x0123), bus.read_byte(addr)];
+	    bus.write_byte(0x0123, 0);
+	    bus.write_byte(addr, 0);
+	    assert_eq!([bus.read_byte(0x0123), bus.read_byte(addr)], old);
+	    assert_eq!(bus.take_log().len(), 2);
+	}
+    }
+
+    #[test]
+    fn unmapped() {
+	let mut bus = with_rom(&GAPPY, 0x2000);
+	assert_eq!(bus.read_byte(0x2400), OPEN_BUS);
+	bus.open_bus = 0x5a;
+	assert_eq!(bus.read_byte(0x8000), 0x5a);
+	assert_eq!(bus.peek(0xffff), 0x5a);
+	bus.write_byte(0x4000, 1);
+	assert_eq!(bus.read_byte(0x4000), 0x5a);
+	assert_eq!(bus.read_byte(0x0123), 0x22);
+	//only reported when asked for
+	bus.verbose = true;
+	bus.read_byte(0x8000);
+	assert!(bus.take_log().is_empty());
+	bus.log_unmapped = true;
+	bus.read_byte(0x8000);
+	bus.write_byte(0x8000, 0);
+	assert_eq!(bus.take_log().len(), 2);
+    }
+
+    //the sound latches pick a prom bank or turn the whole screen red
+    #[test]
+    fn cell_color_latches() {
+	let mut bus = InvBus::with_driver(&INVADPT2);
+	assert_eq!(bus.take_cell_colors(), None);
+	let proms: Vec<u8> = (0..0x800).map(|i| (i % 7) as u8 | 0xf0).collect();
+	let bank = |n: usize| -> Vec<u8> { proms[n * 0x400..(n + 1) * 0x400].iter().map(|c| c & 7).collect() };
+	bus.load_proms(&proms);
+	assert_eq!(bus.take_cell_colors(), Some(bank(0)));
+	assert_eq!(bus.take_cell_colors(), None);
+	bus.write_io_byte(5, 0x20);
+	assert_eq!(bus.take_cell_colors(), Some(bank(1)));
+	bus.write_io_byte(5, 0x20);
+	assert_eq!(bus.take_cell_colors(), None);
+	bus.write_io_byte(3, 0x04);
+	assert_eq!(bus.take_cell_colors(), Some(vec![1; 0x400]));
+	bus.write_io_byte(3, 0);
+	assert_eq!(bus.take_cell_colors(), Some(bank(1)));
+	bus.reset();
+	assert_eq!(bus.take_cell_colors(), Some(bank(0)));
     }
 }
@@ -0,0 +1,191 @@
+use crate::artwork::Align;
+use crate::filter::Upscale;
+use crate::video::{Band, Overlay, WIDTH, HEIGHT};
+use std::env;
+
+const DEFAULT_CONFIG: &str = "invaders.cfg";
+
+//settings come from invaders.cfg ("key = value" lines, # for comments),
+//then from the command line as --key value, which wins
+pub struct Config {
+    pub rom_path: Option<String>,
+    pub game: Option<String>,
+    pub check_crc: bool,
+    pub dip: Option<u8>,
+    pub log_unmapped: bool,
+    pub open_bus: Option<u8>,
+    pub watchdog: usize,
+    pub hiscore: bool,
+    pub overlay: Overlay,
+    pub backdrop: Option<String>,
+    pub backdrop_align: Align,
+    pub backdrop_offset: (i32, i32),
+    pub backdrop_brightness: f32,
+    pub screenshot: Option<String>,
+    pub frames: usize,
+    pub scale: u32,
+    pub integer_scale: bool,
+    pub aspect: f32,
+    pub fullscreen: bool,
+    pub phosphor: f32,
+    pub bloom: f32,
+    pub scanlines: f32,
+    pub upscale: Upscale,
+}
+
+impl Config {
+    pub fn new() -> Self {
+	Config {
+	    rom_path: None,
+	    game: None,
+	    check_crc: true,
+	    dip: None,
+	    log_unmapped: false,
+	    open_bus: None,
+	    watchdog: 0,
+	    hiscore: true,
+	    overlay: Overlay::Midway,
+	    backdrop: None,
+	    backdrop_align: Align::Stretch,
+	    backdrop_offset: (0, 0),
+	    backdrop_brightness: 1.0,
+	    screenshot: None,
+	    frames: 600,
+	    scale: 3,
+	    integer_scale: true,
+	    aspect: 3.0 / 4.0,
+	    fullscreen: false,
+	    phosphor: 0.0,
+	    bloom: 0.0,
+	    scanlines: 0.0,
+	    upscale: Upscale::None,
+	}
+    }
+
+    pub fn from_args() -> Self {
+	let mut cfg = Config::new();
+	let args: Vec<String> = env::args().skip(1).collect();
+	let cfg_path = match args.iter().position(|a| a == "--config") {
+	    Some(i) => args.get(i + 1).expect("--config needs a path").clone(),
+	    None => String::from(DEFAULT_CONFIG),
+	};
+	if let Ok(text) = std::fs::read_to_string(&cfg_path) {
+	    cfg.parse(&text);
+	}
+
+	let mut i = 0;
+	while i < args.len() {
+	    match args[i].strip_prefix("--") {
+		Some("config") => i += 1,
+		Some(key) => {
+		    let val = args.get(i + 1).unwrap_or_else(|| panic!("--{key} needs a value"));
+		    cfg.set(key, val);
+		    i += 1;
+		},
+		None => cfg.rom_path = Some(args[i].clone()),
+	    }
+	    i += 1;
+	}
+	cfg
+    }
+
+    pub fn parse(&mut self, text: &str) {
+	for (n, line) in text.lines().enumerate() {
+	    let line = line.trim();
+	    if line.is_empty() || line.starts_with('#') {
+		continue;
+	    }
+	    match line.split_once('=') {
+		Some((key, val)) => self.set(key.trim(), val.trim()),
+		None => panic!("config line {}: expected key = value", n + 1),
+	    }
+	}
+    }
+
+    pub fn set(&mut self, key: &str, val: &str) {
+	match key {
+	    "rom" => self.rom_path = Some(String::from(val)),
+	    "game" => self.game = Some(String::from(val)),
+	    "check_crc" => self.check_crc = parse_val(key, val),
+	    "dip" => {
+		let v = u8::from_str_radix(val.trim_start_matches("0x"), 16);
+		self.dip = Some(v.unwrap_or_else(|_| panic!("bad dip {val}, expected a hex byte")));
+	    },
+	    "open_bus" => {
+		let v = u8::from_str_radix(val.trim_start_matches("0x"), 16);
+		self.open_bus = Some(v.unwrap_or_else(|_| panic!("bad open_bus {val}, expected a hex byte")));
+	    },
+	    "overlay" => {
+		self.overlay = Overlay::from_name(val)
+		    .unwrap_or_else(|| panic!("unknown overlay {val}, expected bw, midway, taito or custom"));
+	    },
+	    "overlay_band" => {
+		//top bottom rrggbb [left right], adding a band switches to a custom overlay
+		let band = parse_band(val).unwrap_or_else(|| panic!("bad overlay_band {val}"));
+		match &mut self.overlay {
+		    Overlay::Custom(bands) => bands.push(band),
+		    _ => self.overlay = Overlay::Custom(vec![band]),
+		}
+	    },
+	    "backdrop" => self.backdrop = Some(String::from(val)),
+	    "backdrop_align" => {
+		self.backdrop_align = Align::from_name(val)
+		    .unwrap_or_else(|| panic!("unknown alignment {val}, expected stretch, center, top or bottom"));
+	    },
+	    "backdrop_offset" => {
+		let mut f = val.split_whitespace().map(|v| parse_val::<i32>(key, v));
+		self.backdrop_offset = (f.next().unwrap_or(0), f.next().unwrap_or(0));
+	    },
+	    "backdrop_brightness" => self.backdrop_brightness = parse_val(key, val),
+	    "screenshot" => self.screenshot = Some(String::from(val)),
+	    "frames" => self.frames = parse_val(key, val),
+	    "scale" => self.scale = parse_val::<u32>(key, val).max(1),
+	    "integer_scale" => self.integer_scale = parse_val(key, val),
+	    "aspect" => {
+		self.aspect = match val {
+		    "square" => WIDTH as f32 / HEIGHT as f32,
+		    _ => match val.split_once(':') {
+			Some((w, h)) => parse_val::<f32>(key, w) / parse_val::<f32>(key, h),
+			None => panic!("bad aspect {val}, expected square or w:h"),
+		    },
+		};
+	    },
+	    "watchdog" => self.watchdog = parse_val(key, val),
+	    "hiscore" => self.hiscore = parse_val(key, val),
+	    "fullscreen" => self.fullscreen = parse_val(key, val),
+	    "log_unmapped" => self.log_unmapped = parse_val(key, val),
+	    "phosphor" => self.phosphor = parse_val(key, val),
+	    "bloom" => self.bloom = parse_val(key, val),
+	    "scanlines" => self.scanlines = parse_val(key, val),
+	    "upscale" => {
+		self.upscale = Upscale::from_name(val)
+		    .unwrap_or_else(|| panic!("unknown upscaler {val}, expected none or scale2x"));
+	    },
+	    _ => panic!("unknown config option {key}"),
+	}
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+	Config::new()
+    }
+}
+
+fn parse_band(val: &str) -> Option<Band> {
+    let f: Vec<&str> = val.split_whitespace().collect();
+    if f.len() != 3 && f.len() != 5 {
+	return None;
+    }
+    let mut band = Band::rows(f[0].parse().ok()?, f[1].parse().ok()?,
+			      u32::from_str_radix(f[2], 16).ok()?);
+    if f.len() == 5 {
+	band.left = f[3].parse().ok()?;
+	band.right = f[4].parse().ok()?;
+    }
+    Some(band)
+}
+
+fn parse_val<T: std::str::FromStr>(key: &str, val: &str) -> T {
+    val.parse().unwrap_or_else(|_| panic!("bad value {val} for {key}"))
+}
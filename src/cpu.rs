@@ -1,7 +1,11 @@
 extern crate bitflags;
 
-use crate::bus;
-use crate::bus::Bus;
+use crate::bus::{Bus, Cycle, InvBus};
+
+mod cache;
+mod z80;
+
+use cache::CodeCache;
 
 #[derive(Debug)]
 struct Instruction {
@@ -87,6 +91,75 @@ const INSTR_SET_INTEL: [Instruction; 256] = instr_set![
     {0xfc, 3, 11, "CM"}, {0xfd, 3, 17, "*CALL"}, {0xfe, 2, 7, "CPI"}, {0xff, 1, 11, "RST"},
 ];
 
+//the 8085 is a little quicker at most things but slower at calls and
+//stack work, and fills the holes in the 8080 set with its own opcodes
+const INSTR_SET_8085: [Instruction; 256] = instr_set![
+    {0x00, 1, 4, "NOP"}, {0x01, 3, 10, "LXI"}, {0x02, 1, 7, "STAX"}, {0x03, 1, 6, "INX"},
+    {0x04, 1, 4, "INR"}, {0x05, 1, 4, "DCR"}, {0x06, 2, 7, "MVI"}, {0x07, 1, 4, "RLC"},
+    {0x08, 1, 10, "DSUB"}, {0x09, 1, 10, "DAD"}, {0x0a, 1, 7, "LDAX"}, {0x0b, 1, 6, "DCX"},
+    {0x0c, 1, 4, "INR"}, {0x0d, 1, 4, "DCR"}, {0x0e, 2, 7, "MVI"}, {0x0f, 1, 4, "RRC"},
+    {0x10, 1, 7, "ARHL"}, {0x11, 3, 10, "LXI"}, {0x12, 1, 7, "STAX"}, {0x13, 1, 6, "INX"},
+    {0x14, 1, 4, "INR"}, {0x15, 1, 4, "DCR"}, {0x16, 2, 7, "MVI"}, {0x17, 1, 4, "RAL"},
+    {0x18, 1, 10, "RDEL"}, {0x19, 1, 10, "DAD"}, {0x1a, 1, 7, "LDAX"}, {0x1b, 1, 6, "DCX"},
+    {0x1c, 1, 4, "INR"}, {0x1d, 1, 4, "DCR"}, {0x1e, 2, 7, "MVI"}, {0x1f, 1, 4, "RAR"},
+    {0x20, 1, 4, "RIM"}, {0x21, 3, 10, "LXI"}, {0x22, 3, 16, "SHLD"}, {0x23, 1, 6, "INX"},
+    {0x24, 1, 4, "INR"}, {0x25, 1, 4, "DCR"}, {0x26, 2, 7, "MVI"}, {0x27, 1, 4, "DAA"},
+    {0x28, 2, 10, "LDHI"}, {0x29, 1, 10, "DAD"}, {0x2a, 3, 16, "LHLD"}, {0x2b, 1, 6, "DCX"},
+    {0x2c, 1, 4, "INR"}, {0x2d, 1, 4, "DCR"}, {0x2e, 2, 7, "MVI"}, {0x2f, 1, 4, "CMA"},
+    {0x30, 1, 4, "SIM"}, {0x31, 3, 10, "LXI"}, {0x32, 3, 13, "STA"}, {0x33, 1, 6, "INX"},
+    {0x34, 1, 10, "INR"}, {0x35, 1, 10, "DCR"}, {0x36, 2, 10, "MVI"}, {0x37, 1, 4, "STC"},
+    {0x38, 2, 10, "LDSI"}, {0x39, 1, 10, "DAD"}, {0x3a, 3, 13, "LDA"}, {0x3b, 1, 6, "DCX"},
+    {0x3c, 1, 4, "INR"}, {0x3d, 1, 4, "DCR"}, {0x3e, 2, 7, "MVI"}, {0x3f, 1, 4, "CMC"},
+    {0x40, 1, 4, "MOV"}, {0x41, 1, 4, "MOV"}, {0x42, 1, 4, "MOV"}, {0x43, 1, 4, "MOV"},
+    {0x44, 1, 4, "MOV"}, {0x45, 1, 4, "MOV"}, {0x46, 1, 7, "MOV"}, {0x47, 1, 4, "MOV"},
+    {0x48, 1, 4, "MOV"}, {0x49, 1, 4, "MOV"}, {0x4a, 1, 4, "MOV"}, {0x4b, 1, 4, "MOV"},
+    {0x4c, 1, 4, "MOV"}, {0x4d, 1, 4, "MOV"}, {0x4e, 1, 7, "MOV"}, {0x4f, 1, 4, "MOV"},
+    {0x50, 1, 4, "MOV"}, {0x51, 1, 4, "MOV"}, {0x52, 1, 4, "MOV"}, {0x53, 1, 4, "MOV"},
+    {0x54, 1, 4, "MOV"}, {0x55, 1, 4, "MOV"}, {0x56, 1, 7, "MOV"}, {0x57, 1, 4, "MOV"},
+    {0x58, 1, 4, "MOV"}, {0x59, 1, 4, "MOV"}, {0x5a, 1, 4, "MOV"}, {0x5b, 1, 4, "MOV"},
+    {0x5c, 1, 4, "MOV"}, {0x5d, 1, 4, "MOV"}, {0x5e, 1, 7, "MOV"}, {0x5f, 1, 4, "MOV"},
+    {0x60, 1, 4, "MOV"}, {0x61, 1, 4, "MOV"}, {0x62, 1, 4, "MOV"}, {0x63, 1, 4, "MOV"},
+    {0x64, 1, 4, "MOV"}, {0x65, 1, 4, "MOV"}, {0x66, 1, 7, "MOV"}, {0x67, 1, 4, "MOV"},
+    {0x68, 1, 4, "MOV"}, {0x69, 1, 4, "MOV"}, {0x6a, 1, 4, "MOV"}, {0x6b, 1, 4, "MOV"},
+    {0x6c, 1, 4, "MOV"}, {0x6d, 1, 4, "MOV"}, {0x6e, 1, 7, "MOV"}, {0x6f, 1, 4, "MOV"},
+    {0x70, 1, 7, "MOV"}, {0x71, 1, 7, "MOV"}, {0x72, 1, 7, "MOV"}, {0x73, 1, 7, "MOV"},
+    {0x74, 1, 7, "MOV"}, {0x75, 1, 7, "MOV"}, {0x76, 1, 5, "HLT"}, {0x77, 1, 7, "MOV"},
+    {0x78, 1, 4, "MOV"}, {0x79, 1, 4, "MOV"}, {0x7a, 1, 4, "MOV"}, {0x7b, 1, 4, "MOV"},
+    {0x7c, 1, 4, "MOV"}, {0x7d, 1, 4, "MOV"}, {0x7e, 1, 7, "MOV"}, {0x7f, 1, 4, "MOV"},
+    {0x80, 1, 4, "ADD"}, {0x81, 1, 4, "ADD"}, {0x82, 1, 4, "ADD"}, {0x83, 1, 4, "ADD"},
+    {0x84, 1, 4, "ADD"}, {0x85, 1, 4, "ADD"}, {0x86, 1, 7, "ADD"}, {0x87, 1, 4, "ADD"},
+    {0x88, 1, 4, "ADC"}, {0x89, 1, 4, "ADC"}, {0x8a, 1, 4, "ADC"}, {0x8b, 1, 4, "ADC"},
+    {0x8c, 1, 4, "ADC"}, {0x8d, 1, 4, "ADC"}, {0x8e, 1, 7, "ADC"}, {0x8f, 1, 4, "ADC"},
+    {0x90, 1, 4, "SUB"}, {0x91, 1, 4, "SUB"}, {0x92, 1, 4, "SUB"}, {0x93, 1, 4, "SUB"},
+    {0x94, 1, 4, "SUB"}, {0x95, 1, 4, "SUB"}, {0x96, 1, 7, "SUB"}, {0x97, 1, 4, "SUB"},
+    {0x98, 1, 4, "SBB"}, {0x99, 1, 4, "SBB"}, {0x9a, 1, 4, "SBB"}, {0x9b, 1, 4, "SBB"},
+    {0x9c, 1, 4, "SBB"}, {0x9d, 1, 4, "SBB"}, {0x9e, 1, 7, "SBB"}, {0x9f, 1, 4, "SBB"},
+    {0xa0, 1, 4, "ANA"}, {0xa1, 1, 4, "ANA"}, {0xa2, 1, 4, "ANA"}, {0xa3, 1, 4, "ANA"},
+    {0xa4, 1, 4, "ANA"}, {0xa5, 1, 4, "ANA"}, {0xa6, 1, 7, "ANA"}, {0xa7, 1, 4, "ANA"},
+    {0xa8, 1, 4, "XRA"}, {0xa9, 1, 4, "XRA"}, {0xaa, 1, 4, "XRA"}, {0xab, 1, 4, "XRA"},
+    {0xac, 1, 4, "XRA"}, {0xad, 1, 4, "XRA"}, {0xae, 1, 7, "XRA"}, {0xaf, 1, 4, "XRA"},
+    {0xb0, 1, 4, "ORA"}, {0xb1, 1, 4, "ORA"}, {0xb2, 1, 4, "ORA"}, {0xb3, 1, 4, "ORA"},
+    {0xb4, 1, 4, "ORA"}, {0xb5, 1, 4, "ORA"}, {0xb6, 1, 7, "ORA"}, {0xb7, 1, 4, "ORA"},
+    {0xb8, 1, 4, "CMP"}, {0xb9, 1, 4, "CMP"}, {0xba, 1, 4, "CMP"}, {0xbb, 1, 4, "CMP"},
+    {0xbc, 1, 4, "CMP"}, {0xbd, 1, 4, "CMP"}, {0xbe, 1, 7, "CMP"}, {0xbf, 1, 4, "CMP"},
+    {0xc0, 1, 6, "RNZ"}, {0xc1, 1, 10, "POP"}, {0xc2, 3, 7, "JNZ"}, {0xc3, 3, 10, "JMP"},
+    {0xc4, 3, 9, "CNZ"}, {0xc5, 1, 12, "PUSH"}, {0xc6, 2, 7, "ADI"}, {0xc7, 1, 12, "RST"},
+    {0xc8, 1, 6, "RZ"}, {0xc9, 1, 10, "RET"}, {0xca, 3, 7, "JZ"}, {0xcb, 1, 6, "RSTV"},
+    {0xcc, 3, 9, "CZ"}, {0xcd, 3, 18, "CALL"}, {0xce, 2, 7, "ACI"}, {0xcf, 1, 12, "RST"},
+    {0xd0, 1, 6, "RNC"}, {0xd1, 1, 10, "POP"}, {0xd2, 3, 7, "JNC"}, {0xd3, 2, 10, "OUT"},
+    {0xd4, 3, 9, "CNC"}, {0xd5, 1, 12, "PUSH"}, {0xd6, 2, 7, "SUI"}, {0xd7, 1, 12, "RST"},
+    {0xd8, 1, 6, "RC"}, {0xd9, 1, 10, "SHLX"}, {0xda, 3, 7, "JC"}, {0xdb, 2, 10, "IN"},
+    {0xdc, 3, 9, "CC"}, {0xdd, 3, 7, "JNK"}, {0xde, 2, 7, "SBI"}, {0xdf, 1, 12, "RST"},
+    {0xe0, 1, 6, "RPO"}, {0xe1, 1, 10, "POP"}, {0xe2, 3, 7, "JPO"}, {0xe3, 1, 16, "XTHL"},
+    {0xe4, 3, 9, "CPO"}, {0xe5, 1, 12, "PUSH"}, {0xe6, 2, 7, "ANI"}, {0xe7, 1, 12, "RST"},
+    {0xe8, 1, 6, "RPE"}, {0xe9, 1, 6, "PCHL"}, {0xea, 3, 7, "JPE"}, {0xeb, 1, 4, "XCHG"},
+    {0xec, 3, 9, "CPE"}, {0xed, 1, 10, "LHLX"}, {0xee, 2, 7, "XRI"}, {0xef, 1, 12, "RST"},
+    {0xf0, 1, 6, "RP"}, {0xf1, 1, 10, "POP"}, {0xf2, 3, 7, "JP"}, {0xf3, 1, 4, "DI"},
+    {0xf4, 3, 9, "CP"}, {0xf5, 1, 12, "PUSH"}, {0xf6, 2, 7, "ORI"}, {0xf7, 1, 12, "RST"},
+    {0xf8, 1, 6, "RM"}, {0xf9, 1, 6, "SPHL"}, {0xfa, 3, 7, "JM"}, {0xfb, 1, 4, "EI"},
+    {0xfc, 3, 9, "CM"}, {0xfd, 3, 7, "JK"}, {0xfe, 2, 7, "CPI"}, {0xff, 1, 12, "RST"},
+];
+
 bitflags::bitflags! {
     #[derive(Clone, Copy, Debug)]
     struct PSW: u8 {
@@ -107,7 +180,36 @@ impl PSW {
     }
 }
 
-pub struct Cpu {
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Model {
+    I8080,
+    I8085,
+    Z80,
+}
+
+//the 8085's extra interrupt inputs and serial lines, and the Z80's NMI.
+//whatever the cpu is plugged into drives the inputs, sod follows SIM
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Pins {
+    pub trap: bool,
+    pub rst55: bool,
+    pub rst65: bool,
+    pub rst75: bool,
+    pub sid: bool,
+    pub sod: bool,
+    pub nmi: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Irq {
+    Intr, //through the interrupt acknowledge
+    Trap,
+    Vector(u16), //RST 5.5, 6.5 and 7.5 go straight to an address
+    Nmi,
+}
+
+#[derive(Clone)]
+pub struct Cpu<B: Bus = InvBus> {
     a: u8,
     b: u8,
     c: u8,
@@ -119,32 +221,43 @@ pub struct Cpu {
     pub pc: u16,
     f: PSW,
     pub ime: bool,
-    pub bus: bus::InvBus,
+    pub bus: B,
     instr_set: &'static [Instruction; 256],
     pub cycles: usize,
     ei_pend: bool,
+    pub halted: bool,
+    t: usize, //t states into the current instruction
+    model: Model,
+    pub pins: Pins,
+    last_pins: Pins, //for the edge triggered inputs
+    masks: u8, //RST 5.5, 6.5 and 7.5 in the low bits, as SIM sets them
+    i75: bool, //RST 7.5 latches until it's taken or cleared
+    trap_latch: bool,
+    trap_ie: Option<bool>, //ime from before a TRAP, for the next RIM
+    alt: [u16; 4], //AF' BC' DE' HL'
+    ix: u16,
+    iy: u16,
+    i: u8,
+    r: u8,
+    im: u8,
+    iff2: bool,
+    wz: u16, //the internal MEMPTR, it shows up in BIT n,(HL) flags
+    pub predecode: bool, //run 8080 and 8085 code through the decoded handlers
+    code: CodeCache<B>,
 }
 
 impl Cpu {
     pub fn new() -> Self {
-	/*if cpm {
-	    Cpu {
-	    a: 0,
-	    b: 0,
-	    c: 0,
-	    d: 0,
-	    e: 0,
-	    h: 0,
-	    l: 0,
-	    sp: 0,
-	    pc: 0,
-	    f: PSW::empty() | PSW::F1,
-	    ime: false,
-	    bus: bus::CpmBus::new(),
-	    instr_set: &INSTR_SET_INTEL,
-	    cycles: 0,
-	    }
-	} else {*/
+	Cpu::with_model(Model::I8080)
+    }
+
+    pub fn with_model(model: Model) -> Self {
+	Cpu::with_bus(InvBus::new(), model)
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    pub fn with_bus(bus: B, model: Model) -> Self {
 	Cpu {
 	    a: 0,
 	    b: 0,
@@ -155,19 +268,70 @@ impl Cpu {
 	    l: 0,
 	    sp: 0,
 	    pc: 0,
-	    f: PSW::empty() | PSW::F1,
+	    f: reset_flags(model),
 	    ime: false,
-	    bus: bus::InvBus::new(),
-	    instr_set: &INSTR_SET_INTEL,
+	    bus,
+	    instr_set: match model {
+		Model::I8080 => &INSTR_SET_INTEL,
+		Model::I8085 => &INSTR_SET_8085,
+		Model::Z80 => &INSTR_SET_INTEL, //unused, the z80 decodes for itself
+	    },
 	    cycles: 0,
 	    ei_pend: false,
-	    }
-	//}
+	    halted: false,
+	    t: 0,
+	    model,
+	    pins: Pins::default(),
+	    last_pins: Pins::default(),
+	    masks: 7,
+	    i75: false,
+	    trap_latch: false,
+	    trap_ie: None,
+	    alt: [0; 4],
+	    ix: 0,
+	    iy: 0,
+	    i: 0,
+	    r: 0,
+	    im: 0,
+	    iff2: false,
+	    wz: 0,
+	    predecode: true,
+	    code: CodeCache::new(),
+	}
     }
 
     pub fn reset(&mut self) {
 	self.pc = 0;
 	self.ime = false;
+	self.ei_pend = false;
+	self.halted = false;
+	self.masks = 7;
+	self.i75 = false;
+	self.trap_latch = false;
+	self.trap_ie = None;
+	self.pins.sod = false;
+	self.iff2 = false;
+	self.im = 0;
+	self.i = 0;
+	self.r = 0;
+	self.code.flush();
+	self.bus.reset();
+    }
+
+    //halted with interrupts off, only a reset will get it going again.
+    //an 8085 can always be woken by TRAP
+    pub fn stuck(&self) -> bool {
+	self.halted && !self.ime && self.model == Model::I8080
+    }
+
+    //registers come up as zero here, the real chip leaves them random
+    pub fn power_on(&mut self) {
+	(self.a, self.b, self.c, self.d, self.e, self.h, self.l) = (0, 0, 0, 0, 0, 0, 0);
+	self.sp = 0;
+	self.f = reset_flags(self.model);
+	(self.alt, self.ix, self.iy, self.wz) = ([0; 4], 0, 0, 0);
+	self.bus.power_on();
+	self.reset();
     }
 
     fn read_rp(&self, rp: u8) -> u16 {
@@ -186,6 +350,20 @@ impl Cpu {
 	((rph as u16) << 8) | rpl as u16
     }
 
+    //register by its 3 bit code, 6 is the byte at (HL)
+    fn read_reg(&mut self, r: u8, hlptr: u16) -> u8 {
+	match r {
+	    0b000 => self.b,
+	    0b001 => self.c,
+	    0b010 => self.d,
+	    0b011 => self.e,
+	    0b100 => self.h,
+	    0b101 => self.l,
+	    0b110 => self.read(Cycle::Read, hlptr),
+	    _ => self.a,
+	}
+    }
+
     fn write_rp(&mut self, rp: u8, data: u16) {
 	let hi = ((data >> 8) & 0xff) as u8;
 	let lo = (data & 0xff) as u8;
@@ -212,14 +390,14 @@ impl Cpu {
     fn push_word(&mut self, data: u16) {
 	let hi = ((data & 0xff00) >> 8) as u8;
 	let lo = (data & 0x00ff) as u8;
-	self.bus.write_byte(self.sp.wrapping_sub(1), hi);
-	self.bus.write_byte(self.sp.wrapping_sub(2), lo);
+	self.write(Cycle::StackWrite, self.sp.wrapping_sub(1), hi);
+	self.write(Cycle::StackWrite, self.sp.wrapping_sub(2), lo);
 	self.sp = self.sp.wrapping_sub(2);
     }
 
     fn pop_word(&mut self) -> u16 {
-	let lo = self.bus.read_byte(self.sp);
-	let hi = self.bus.read_byte(self.sp.wrapping_add(1));
+	let lo = self.read(Cycle::StackRead, self.sp);
+	let hi = self.read(Cycle::StackRead, self.sp.wrapping_add(1));
 	self.sp = self.sp.wrapping_add(2);
 	((hi as u16) << 8) | (lo as u16)
     }
@@ -232,7 +410,7 @@ impl Cpu {
 	    3 => self.e = s,
 	    4 => self.h = s,
 	    5 => self.l = s,
-	    6 => self.bus.write_byte(hlptr, s),
+	    6 => self.write(Cycle::Write, hlptr, s),
 	    _ => self.a = s,
 	};
     }
@@ -285,18 +463,93 @@ impl Cpu {
 	self.f.set(PSW::Z, (tmp & 0xff) == 0);
 	self.f.set(PSW::S, (tmp & 0x80) != 0);
 	self.f.set(PSW::P, (((tmp & 0xff) as u8).count_ones() % 2) == 0);
+	if self.model == Model::I8085 && (op < 4 || op == 7) {
+	    //signed overflow in V, and K as the signed less than it gives.
+	    //how the real chip sets K here is only partly known
+	    let s2 = if op < 2 { s } else { !s };
+	    let v = ((self.a ^ tmp as u8) & (s2 ^ tmp as u8) & 0x80) != 0;
+	    self.f.set(PSW::F1, v);
+	    self.f.set(PSW::F5, v != ((tmp & 0x80) != 0));
+	}
 	if op != 7 { //CMP doesn't modify a
 	    self.a = tmp as u8;
 	}
     }
 
+    fn inr(&mut self, d_bits: u8) {
+	let hlptr = self.read_rp(2);
+	let d = self.read_reg(d_bits, hlptr);
+	let tmp = d.wrapping_add(1) as u16;
+	self.f.set(PSW::Z, tmp == 0);
+	self.f.set(PSW::S, (tmp & 0x80) != 0);
+	self.f.set(PSW::P, (((tmp & 0xff) as u8).count_ones() % 2) == 0);
+	self.f.set(PSW::A, ((d & 0x0f).wrapping_add(1)) > 0x0f);
+	let tmp = tmp as u8;
+	match d_bits {
+	    0 => self.b = tmp,
+	    1 => self.c = tmp,
+	    2 => self.d = tmp,
+	    3 => self.e = tmp,
+	    4 => self.h = tmp,
+	    5 => self.l = tmp,
+	    6 => self.write(Cycle::Write, hlptr, tmp),
+	    _ => self.a = tmp,
+	};
+    }
+
+    fn dcr(&mut self, d_bits: u8) {
+	let hlptr = self.read_rp(2);
+	let d = self.read_reg(d_bits, hlptr);
+	let tmp = d.wrapping_sub(1) as u16;
+	self.f.set(PSW::Z, tmp == 0);
+	self.f.set(PSW::S, (tmp & 0x80) != 0);
+	self.f.set(PSW::P, (((tmp & 0xff) as u8).count_ones() % 2) == 0);
+	self.f.set(PSW::A, (d & 0x0f) != 0);
+	let tmp = tmp as u8;
+	match d_bits {
+	    0 => self.b = tmp,
+	    1 => self.c = tmp,
+	    2 => self.d = tmp,
+	    3 => self.e = tmp,
+	    4 => self.h = tmp,
+	    5 => self.l = tmp,
+	    6 => self.write(Cycle::Write, hlptr, tmp),
+	    _ => self.a = tmp,
+	};
+    }
+
+    fn inx(&mut self, rp: u8) {
+	let tmp = self.read_rp(rp);
+	self.write_rp(rp, tmp.wrapping_add(1));
+	if self.model == Model::I8085 {
+	    self.f.set(PSW::F5, tmp == 0xffff); //K, for counting loops
+	}
+    }
+
+    fn dcx(&mut self, rp: u8) {
+	let tmp = self.read_rp(rp);
+	self.write_rp(rp, tmp.wrapping_sub(1));
+	if self.model == Model::I8085 {
+	    self.f.set(PSW::F5, tmp == 0);
+	}
+    }
+
     fn branch(&mut self, op: u8, c: u8, uncond: u8, addr: u16) {
-	let c2 = if uncond != 0 {
-	    8
-	} else {
-	    c
+	let cond = uncond != 0 || self.cond(c);
+	match op {
+	    0 => self.ret(cond, uncond),
+	    1 => self.jump(cond, uncond, addr),
+	    2 => self.call(cond, uncond, addr),
+	    _ => { //RST
+		self.push_word(self.pc);
+		self.pc = (c << 3) as u16;
+	    },
 	};
-	let cond: bool = match c2 {
+    }
+
+    //the condition codes are the same on all three chips
+    fn cond(&self, cc: u8) -> bool {
+	match cc {
 	    0 => !self.f.contains(PSW::Z),
 	    1 => self.f.contains(PSW::Z),
 	    2 => !self.f.contains(PSW::C),
@@ -304,43 +557,273 @@ impl Cpu {
 	    4 => !self.f.contains(PSW::P),
 	    5 => self.f.contains(PSW::P),
 	    6 => !self.f.contains(PSW::S),
-	    7 => self.f.contains(PSW::S),
-	    _ => true,
-	};
-	match op {
-	    0 => { //RET
-		if cond {
-		    self.pc = self.pop_word();
-		    self.cycles += 6;
+	    _ => self.f.contains(PSW::S),
+	}
+    }
+
+    fn ret(&mut self, cond: bool, uncond: u8) {
+	if cond {
+	    self.pc = self.pop_word();
+	    if uncond == 0 {
+		self.cycles += 6; //the table has the not taken time
+	    }
+	}
+    }
+
+    fn jump(&mut self, cond: bool, uncond: u8, addr: u16) {
+	if cond {
+	    self.pc = addr;
+	    if uncond == 0 && self.model == Model::I8085 {
+		self.cycles += 3;
+	    }
+	}
+    }
+
+    fn call(&mut self, cond: bool, uncond: u8, addr: u16) {
+	if cond {
+	    self.push_word(self.pc);
+	    self.pc = addr;
+	    if uncond == 0 {
+		self.cycles += if self.model == Model::I8085 { 9 } else { 6 };
+	    }
+	}
+    }
+
+    //the interrupt to take before the next instruction, if any
+    fn pending_irq(&mut self) -> Option<Irq> {
+	if self.model == Model::Z80 {
+	    let nmi = self.pins.nmi && !self.last_pins.nmi;
+	    self.last_pins = self.pins;
+	    if nmi {
+		return Some(Irq::Nmi);
+	    }
+	}
+	if self.model == Model::I8085 {
+	    let p = self.pins;
+	    self.i75 |= p.rst75 && !self.last_pins.rst75;
+	    self.trap_latch |= p.trap && !self.last_pins.trap;
+	    self.last_pins = p;
+	    if self.trap_latch && p.trap {
+		return Some(Irq::Trap);
+	    }
+	    if self.ime {
+		if self.i75 && self.masks & 4 == 0 {
+		    return Some(Irq::Vector(0x3c));
 		}
-	    },
-	    1 => { //JMP
-		if cond {
-		    self.pc = addr;
+		if p.rst65 && self.masks & 2 == 0 {
+		    return Some(Irq::Vector(0x34));
 		}
+		if p.rst55 && self.masks & 1 == 0 {
+		    return Some(Irq::Vector(0x2c));
+		}
+	    }
+	}
+	if self.ime && self.bus.irq_pending() {
+	    Some(Irq::Intr)
+	} else {
+	    None
+	}
+    }
+
+    //the 8085's own interrupts don't acknowledge, they act like an RST
+    fn vector(&mut self, addr: u16) -> usize {
+	let oldcycles = self.cycles;
+	self.ime = false;
+	self.cycles += 12;
+	self.t = 6;
+	self.push_word(self.pc);
+	self.pc = addr;
+	self.cycles - oldcycles
+    }
+
+    fn rim(&mut self) -> u8 {
+	let ie = self.trap_ie.take().unwrap_or(self.ime);
+	(self.pins.sid as u8) << 7 | (self.i75 as u8) << 6 | (self.pins.rst65 as u8) << 5
+	    | (self.pins.rst55 as u8) << 4 | (ie as u8) << 3 | self.masks
+    }
+
+    fn sim(&mut self, data: u8) {
+	if data & 0x08 != 0 {
+	    self.masks = data & 7;
+	}
+	if data & 0x10 != 0 {
+	    self.i75 = false;
+	}
+	if data & 0x40 != 0 {
+	    self.pins.sod = data & 0x80 != 0;
+	}
+    }
+
+    //the opcodes the 8085 put in the 8080's spare NOP, JMP and CALL slots.
+    //Intel never documented them, F1 and F5 are the V and K flags they use
+    fn exec_8085(&mut self, opcode: u8, op1: u8, opw: u16) {
+	match opcode {
+	    0x08 => { //DSUB
+		let hl = self.read_rp(2);
+		let bc = self.read_rp(0);
+		let tmp = hl.wrapping_sub(bc);
+		self.f.set(PSW::C, bc > hl);
+		self.f.set(PSW::A, (bc & 0xf) > (hl & 0xf));
+		self.f.set(PSW::Z, tmp == 0);
+		self.f.set(PSW::S, (tmp & 0x8000) != 0);
+		self.f.set(PSW::P, (tmp as u8).count_ones() & 1 == 0);
+		self.f.set(PSW::F1, ((hl ^ bc) & (hl ^ tmp) & 0x8000) != 0);
+		self.write_rp(2, tmp);
+	    },
+	    0x10 => { //ARHL
+		let hl = self.read_rp(2);
+		self.f.set(PSW::C, (hl & 1) != 0);
+		self.write_rp(2, (hl >> 1) | (hl & 0x8000));
+	    },
+	    0x18 => { //RDEL
+		let de = self.read_rp(1);
+		let tmp = (de << 1) | self.f.contains(PSW::C) as u16;
+		self.f.set(PSW::C, (de & 0x8000) != 0);
+		self.f.set(PSW::F1, ((de ^ tmp) & 0x8000) != 0);
+		self.write_rp(1, tmp);
+	    },
+	    0x20 => { //RIM
+		self.a = self.rim();
 	    },
-	    2 => { //CALL
-		if cond {
+	    0x28 => { //LDHI
+		let tmp = self.read_rp(2).wrapping_add(op1 as u16);
+		self.write_rp(1, tmp);
+	    },
+	    0x30 => { //SIM
+		self.sim(self.a);
+	    },
+	    0x38 => { //LDSI
+		self.write_rp(1, self.sp.wrapping_add(op1 as u16));
+	    },
+	    0xcb => { //RSTV
+		if self.f.contains(PSW::F1) {
 		    self.push_word(self.pc);
-		    self.pc = addr;
+		    self.pc = 0x40;
 		    self.cycles += 6;
 		}
 	    },
-	    _ => { //RST
-		self.push_word(self.pc);
-		self.pc = (c << 3) as u16;
+	    0xd9 => { //SHLX
+		let de = self.read_rp(1);
+		self.write(Cycle::Write, de, self.l);
+		self.write(Cycle::Write, de.wrapping_add(1), self.h);
+	    },
+	    0xed => { //LHLX
+		let de = self.read_rp(1);
+		self.l = self.read(Cycle::Read, de);
+		self.h = self.read(Cycle::Read, de.wrapping_add(1));
 	    },
+	    _ => { //JNK, JK
+		if self.f.contains(PSW::F5) == (opcode == 0xfd) {
+		    self.pc = opw;
+		    self.cycles += 3;
+		}
+	    },
+	}
+    }
+
+    //the 8085 decides a conditional jump or call before it reads the high
+    //byte and leaves it alone when it won't be taken, that's where the 7
+    //and 9 states in its table come from. None for everything else
+    fn taken_8085(&self, opcode: u8) -> Option<bool> {
+	if self.model != Model::I8085 {
+	    return None;
+	}
+	match opcode {
+	    0xdd | 0xfd => Some(self.f.contains(PSW::F5) == (opcode == 0xfd)), //JNK, JK
+	    0xc2..=0xfc if opcode & 7 == 2 || opcode & 7 == 4 => Some(self.cond((opcode >> 3) & 7)),
+	    _ => None,
+	}
+    }
+
+    //the opcode and its operands. only the bytes the instruction has are
+    //fetched, the next ones could be anything including a device that
+    //notices being read
+    fn fetch_instr(&mut self, inta: bool) -> [u8; 3] {
+	let opcode = self.fetch(inta, 0);
+	let mut bytes = self.instr_set[opcode as usize].bytes;
+	if !inta && self.taken_8085(opcode) == Some(false) {
+	    bytes = 2;
+	}
+	let op1 = if bytes > 1 { self.fetch(inta, 1) } else { 0 };
+	let op2 = if bytes > 2 { self.fetch(inta, 2) } else { 0 };
+	[opcode, op1, op2]
+    }
+
+    //byte n of the next instruction, from memory or the interrupt acknowledge
+    fn fetch(&mut self, inta: bool, n: u16) -> u8 {
+	let addr = self.pc.wrapping_add(n);
+	let kind = match (inta, n) {
+	    (true, _) => Cycle::Inta,
+	    (false, 0) => Cycle::Fetch,
+	    _ => Cycle::Read,
 	};
+	self.begin_cycle(kind, addr);
+	let data = if inta {
+	    self.bus.inta(n)
+	} else {
+	    self.bus.read_byte(addr)
+	};
+	self.t += if n == 0 { m1_states(self.model, data) } else { 3 };
+	data
+    }
+
+    //tell the bus a machine cycle is starting and hold for any wait states
+    fn begin_cycle(&mut self, kind: Cycle, addr: u16) {
+	let wait = self.bus.cycle(kind, addr, self.t);
+	self.cycles += wait;
+	self.t += wait;
+    }
+
+    fn read(&mut self, kind: Cycle, addr: u16) -> u8 {
+	self.begin_cycle(kind, addr);
+	self.t += 3;
+	self.bus.read_byte(addr)
+    }
+
+    fn write(&mut self, kind: Cycle, addr: u16, data: u8) {
+	self.begin_cycle(kind, addr);
+	self.t += 3;
+	self.bus.write_byte(addr, data);
+	self.code.wrote(&self.bus, addr);
     }
 
     pub fn step(&mut self) -> usize {
+	if self.bus.take_reset() {
+	    self.reset();
+	}
+	let irq = self.pending_irq();
+	if self.halted {
+	    //idle in 4 cycle steps so the rest of the machine keeps going
+	    if irq.is_none() {
+		self.cycles += 4;
+		return 4;
+	    }
+	    self.halted = false; //pc is already past the HLT, which is where the interrupt returns to
+	}
+	self.t = 0;
+	if self.model == Model::Z80 {
+	    return self.step_z80(irq);
+	}
+	match irq {
+	    Some(Irq::Trap) => {
+		self.trap_latch = false;
+		self.trap_ie = Some(self.ime);
+		return self.vector(0x24);
+	    },
+	    Some(Irq::Vector(addr)) => {
+		if addr == 0x3c {
+		    self.i75 = false;
+		}
+		return self.vector(addr);
+	    },
+	    _ => {},
+	}
 	let oldcycles = self.cycles;
-	let mut opcode: u8 = self.bus.read_byte(self.pc);
-	if self.bus.irq && self.ime {
-	    self.pc -= 1; //1 byte will be added later, want to ret back to interrupted instr
+	//an accepted interrupt runs whatever the controller hands over, and pc
+	//stays on the interrupted instruction for a CALL or RST to push
+	let inta = irq == Some(Irq::Intr);
+	if inta {
 	    self.ime = false;
-	    self.bus.irq = false;
-	    opcode = self.bus.irq_vec;
 	}
 	if self.ei_pend {
 	    self.ime = true;
@@ -349,57 +832,60 @@ impl Cpu {
 	    //after this instruction runs so we have effectively
 	    //gotten the one instruction delay specified in the manual
 	}
-	//todo: decode extended opcodes
+	if self.predecode && !inta {
+	    let e = match self.code.get(self.pc) {
+		Some(e) => {
+		    self.cycles += e.wait as usize;
+		    self.t += e.states as usize;
+		    *e
+		},
+		None => self.decode(),
+	    };
+	    self.cycles += e.cycles as usize;
+	    self.pc = self.pc.wrapping_add(e.len as u16);
+	    (e.run)(self, e.arg);
+	    return self.cycles - oldcycles;
+	}
+	let [opcode, op1, op2] = self.fetch_instr(inta);
 	let instr: &Instruction = &self.instr_set[opcode as usize];
 	self.cycles += instr.cycles as usize;
+	let opw = ((op2 as u16) << 8) | op1 as u16;
+	if !inta {
+	    self.pc = self.pc.wrapping_add(instr.bytes as u16);
+	}
+	self.exec(opcode, opw);
+	self.cycles - oldcycles
+    }
+
+    //everything about an instruction after it's fetched, op1 is the low
+    //byte of opw
+    fn exec(&mut self, opcode: u8, opw: u16) {
+	let op1 = opw as u8;
 	let d_bits = (opcode >> 3) & 7;
-	let s = opcode & 7;
+	let s_bits = opcode & 7;
 	let rp = (opcode >> 4) & 3;
 	let c = d_bits;
 	let n = d_bits;
 	let hlptr = self.read_rp(2);
 
-	let s = match s {
-	    0b000 => self.b,
-	    0b001 => self.c,
-	    0b010 => self.d,
-	    0b011 => self.e,
-	    0b100 => self.h,
-	    0b101 => self.l,
-	    0b110 => self.bus.read_byte(hlptr),
-	    _ => self.a,
-	};
-
-	let d = match d_bits {
-	    0b000 => self.b,
-	    0b001 => self.c,
-	    0b010 => self.d,
-	    0b011 => self.e,
-	    0b100 => self.h,
-	    0b101 => self.l,
-	    0b110 => self.bus.read_byte(hlptr), //bad trouble, but only used in like 4 instructions so its ok for now
-	    _ => self.a,
-	};
-
-	let op1 = self.bus.read_byte(self.pc.wrapping_add(1));
-	let op2 = self.bus.read_byte(self.pc.wrapping_add(2));
-	let opw = ((op2 as u16) << 8) | op1 as u16;
-
 	//println!("A {:02X} F {:02X} B {:02X} C {:02X} D {:02X} E {:02X} H {:02X} L {:02X} SP {:04X}, CYC: {} ime {}",
 	//	 self.a, self.f.as_u8(), self.b, self.c, self.d, self.e, self.h, self.l, self.sp, self.cycles, self.ime);
-	//disas(self.pc, instr.opcode, op1, op2, opw);
-
-	self.pc = self.pc.wrapping_add(instr.bytes as u16);
+	//disas(self.pc, opcode, op1, (opw >> 8) as u8, opw);
 
 	match opcode {
+	    0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 |
+	    0xcb | 0xd9 | 0xdd | 0xed | 0xfd if self.model == Model::I8085 => {
+		self.exec_8085(opcode, op1, opw);
+	    },
 	    0x00 | 0x10 | 0x20 | 0x30 |
 	    0x08 | 0x18 | 0x28 | 0x38 => { //NOP
 		//nothing
 	    },
 	    0x76 => { //HLT
-		return 0; //todo proper behavior
+		self.halted = true;
 	    },
 	    0x40..=0x7f => { //MOV r1, r2
+		let s = self.read_reg(s_bits, hlptr);
 		self.movb(d_bits, s, hlptr);
 	    },
 	    0x06 | 0x16 | 0x26 | 0x36 |
@@ -411,27 +897,27 @@ impl Cpu {
 	    },
 	    0x02 | 0x12 => { //STAX
 		let tmp = self.read_rp(rp);
-		self.bus.write_byte(tmp, self.a);
+		self.write(Cycle::Write, tmp, self.a);
 	    },
 	    0x0a | 0x1a => { //LDAX
 		let tmp = self.read_rp(rp);
-		self.a = self.bus.read_byte(tmp);
+		self.a = self.read(Cycle::Read, tmp);
 	    },
 	    0x22 => { //SHLD
 		let tmp = self.read_rp(2);
-		self.bus.write_byte(opw, (tmp & 0xff) as u8);
-		self.bus.write_byte(opw + 1, ((tmp >> 8) & 0xff) as u8);
+		self.write(Cycle::Write, opw, (tmp & 0xff) as u8);
+		self.write(Cycle::Write, opw.wrapping_add(1), ((tmp >> 8) & 0xff) as u8);
 	    },
 	    0x2a => { //LHLD
-		let lo = self.bus.read_byte(opw);
-		let hi = self.bus.read_byte(opw + 1);
+		let lo = self.read(Cycle::Read, opw);
+		let hi = self.read(Cycle::Read, opw.wrapping_add(1));
 		self.write_rp(2, ((hi as u16) << 8) | lo as u16);
 	    },
 	    0x32 => { //STA
-		self.bus.write_byte(opw, self.a);
+		self.write(Cycle::Write, opw, self.a);
 	    },
 	    0x3a => { //LDA
-		self.a = self.bus.read_byte(opw);
+		self.a = self.read(Cycle::Read, opw);
 	    },
 	    0xc5 | 0xd5 | 0xe5 | 0xf5 => { //PUSH
 		let mut tmp = self.read_rp(rp);
@@ -447,9 +933,11 @@ impl Cpu {
 		} else {
 		    self.a = ((tmp & 0xff00) >> 8) as u8;
 		    self.f = PSW::from_bits((tmp & 0x00ff) as u8).unwrap();
-		    self.f.insert(PSW::F1);
-		    self.f.remove(PSW::F5);
-		    self.f.remove(PSW::F3);
+		    if self.model == Model::I8080 {
+			self.f.insert(PSW::F1);
+			self.f.remove(PSW::F5);
+			self.f.remove(PSW::F3);
+		    }
 		}
 	    },
 	    0xe3 => { //XTHL
@@ -470,12 +958,10 @@ impl Cpu {
 		self.e = tmp;
 	    },
 	    0x03 | 0x13 | 0x23 | 0x33 => { //INX
-		let tmp = self.read_rp(rp);
-		self.write_rp(rp, tmp.wrapping_add(1));
+		self.inx(rp);
 	    },
 	    0x0b | 0x1b | 0x2b | 0x3b => { //DCX
-		let tmp = self.read_rp(rp);
-		self.write_rp(rp, tmp.wrapping_sub(1));
+		self.dcx(rp);
 	    },
 	    0x09 | 0x19 | 0x29 | 0x39 => { //DAD
 		let hltmp = self.read_rp(2) as u32;
@@ -486,6 +972,7 @@ impl Cpu {
 	    },
 	    0x80..=0xbf => { //aluops a, r
 		let op_bits: u8 = (opcode & 0x38) >> 3;
+		let s = self.read_reg(s_bits, hlptr);
 		self.aluop(op_bits, s);
 	    },
 	    0xc6 | 0xd6 | 0xe6 | 0xf6 |
@@ -495,47 +982,11 @@ impl Cpu {
 	    },
 	    0x04 | 0x14 | 0x24 | 0x34 |
 	    0x0c | 0x1c | 0x2c | 0x3c => { //INR
-		let mut tmp = d.wrapping_add(1) as u16;
-		if d_bits == 6 {
-		    tmp = self.bus.read_byte(hlptr).wrapping_add(1) as u16;
-		}
-		self.f.set(PSW::Z, tmp == 0);
-		self.f.set(PSW::S, (tmp & 0x80) != 0);
-		self.f.set(PSW::P, (((tmp & 0xff) as u8).count_ones() % 2) == 0);
-		self.f.set(PSW::A, ((d & 0x0f).wrapping_add(1)) > 0x0f);
-		let tmp = tmp as u8;
-		match d_bits {
-		    0 => self.b = tmp,
-		    1 => self.c = tmp,
-		    2 => self.d = tmp,
-		    3 => self.e = tmp,
-		    4 => self.h = tmp,
-		    5 => self.l = tmp,
-		    6 => self.bus.write_byte(hlptr, tmp),
-		    _ => self.a = tmp,
-		};
+		self.inr(d_bits);
 	    },
 	    0x05 | 0x15 | 0x25 | 0x35 |
 	    0x0d | 0x1d | 0x2d | 0x3d => { //DCR
-		let mut tmp = d.wrapping_sub(1) as u16;
-		if d_bits == 6 {
-		    tmp = self.bus.read_byte(hlptr).wrapping_sub(1) as u16;
-		}
-		self.f.set(PSW::Z, tmp == 0);
-		self.f.set(PSW::S, (tmp & 0x80) != 0);
-		self.f.set(PSW::P, (((tmp & 0xff) as u8).count_ones() % 2) == 0);
-		self.f.set(PSW::A, (d & 0x0f) != 0);
-		let tmp = tmp as u8;
-		match d_bits {
-		    0 => self.b = tmp,
-		    1 => self.c = tmp,
-		    2 => self.d = tmp,
-		    3 => self.e = tmp,
-		    4 => self.h = tmp,
-		    5 => self.l = tmp,
-		    6 => self.bus.write_byte(hlptr, tmp),
-		    _ => self.a = tmp,
-		};
+		self.dcr(d_bits);
 	    },
 	    0x07 => { //RLC
 		self.f.set(PSW::C, ((self.a & 0x80) >> 7) != 0);
@@ -598,9 +1049,11 @@ impl Cpu {
 		self.branch((opcode & 6) >> 1, c, opcode & 1, opw);
 	    },
 	    0xd3 => { //OUT
+		self.begin_cycle(Cycle::IoWrite, (op1 as u16) << 8 | op1 as u16);
 		self.bus.write_io_byte(op1, self.a);
 	    },
 	    0xdb => { //IN
+		self.begin_cycle(Cycle::IoRead, (op1 as u16) << 8 | op1 as u16);
 		self.a = self.bus.read_io_byte(op1);
 	    },
 	    0xf3 => { //DI
@@ -610,8 +1063,47 @@ impl Cpu {
 		self.ei_pend = true;
 	    },
 	};
-	    
-	self.cycles - oldcycles
+    }
+}
+
+//length of the opcode fetch, the ones that do some work on the
+//address bus or a register transfer take a state longer
+fn m1_states(model: Model, opcode: u8) -> usize {
+    if model == Model::I8085 {
+	//the 8085 takes 6 for anything that moves the stack pointer or
+	//a 16 bit register and 4 for the rest
+	return match opcode {
+	    0x03 | 0x13 | 0x23 | 0x33 | 0x0b | 0x1b | 0x2b | 0x3b => 6, //INX, DCX
+	    0xc0..=0xff if opcode & 3 == 0 || opcode & 7 == 7 => 6, //Rcc, Ccc, RST
+	    0xc5..=0xff if opcode & 7 == 5 && opcode & 8 == 0 => 6, //PUSH
+	    0xcb | 0xcd | 0xe9 | 0xf9 => 6, //RSTV, CALL, PCHL, SPHL
+	    _ => 4,
+	};
+    }
+    match opcode {
+	0x40..=0x7f if opcode & 7 != 6 && opcode & 0x38 != 0x30 => 5, //MOV r, r
+	0x03 | 0x13 | 0x23 | 0x33 | 0x0b | 0x1b | 0x2b | 0x3b => 5, //INX, DCX
+	0x04..=0x3d if opcode & 6 == 4 && opcode & 0x38 != 0x30 => 5, //INR r, DCR r
+	0xc0..=0xff if opcode & 7 == 0 => 5, //Rcc
+	0xc4..=0xff if opcode & 7 == 4 => 5, //Ccc
+	0xc5..=0xff if opcode & 7 == 5 && opcode & 8 == 0 => 5, //PUSH
+	0xc7..=0xff if opcode & 7 == 7 => 5, //RST
+	0xcd | 0xdd | 0xed | 0xfd | 0xe9 | 0xf9 => 5, //CALL, PCHL, SPHL
+	_ => 4,
+    }
+}
+
+//the 8080 always has F1 set, the 8085 uses it as V and the Z80 as N
+fn reset_flags(model: Model) -> PSW {
+    match model {
+	Model::I8080 => PSW::empty() | PSW::F1,
+	Model::I8085 | Model::Z80 => PSW::empty(),
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+	Cpu::new()
     }
 }
 
@@ -1138,3 +1630,469 @@ fn disas_zilog(pc: u16, opcode: u8, op1: u8, op2: u8, opw: u16) {
 	0xFF => println!("RST $38"),
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::noise;
+    use crate::bus::InterruptController;
+
+    //64K of ram that keeps a list of every machine cycle and counts the
+    //accesses, with an interrupt controller that hands over irq_instr
+    #[derive(Clone)]
+    struct TestBus {
+	mem: Vec<u8>,
+	cycles: Vec<Cycle>,
+	reads: usize,
+	writes: usize,
+	io: usize,
+	irq: bool,
+	irq_instr: Vec<u8>,
+    }
+
+    impl TestBus {
+	fn new() -> Self {
+	    TestBus {
+		mem: vec![0; 0x10000],
+		cycles: Vec::new(),
+		reads: 0,
+		writes: 0,
+		io: 0,
+		irq: false,
+		irq_instr: Vec::new(),
+	    }
+	}
+    }
+
+    impl InterruptController for TestBus {
+	fn irq_pending(&self) -> bool {
+	    self.irq
+	}
+
+	fn inta(&mut self, n: u16) -> u8 {
+	    if n == 0 {
+		self.irq = false;
+	    }
+	    self.irq_instr[n as usize]
+	}
+    }
+
+    impl Bus for TestBus {
+	fn read_byte(&mut self, addr: u16) -> u8 {
+	    self.reads += 1;
+	    self.mem[addr as usize]
+	}
+
+	fn read_word(&mut self, addr: u16) -> u16 {
+	    self.read_byte(addr) as u16 | (self.read_byte(addr.wrapping_add(1)) as u16) << 8
+	}
+
+	fn write_byte(&mut self, addr: u16, data: u8) {
+	    self.writes += 1;
+	    self.mem[addr as usize] = data;
+	}
+
+	fn write_word(&mut self, addr: u16, data: u16) {
+	    self.write_byte(addr, data as u8);
+	    self.write_byte(addr.wrapping_add(1), (data >> 8) as u8);
+	}
+
+	fn read_io_byte(&mut self, _port: u8) -> u8 {
+	    self.io += 1;
+	    0
+	}
+
+	fn write_io_byte(&mut self, _port: u8, _data: u8) {
+	    self.io += 1;
+	}
+
+	fn load_bin(&mut self, offs: usize, buf: &[u8]) {
+	    self.mem[offs..offs + buf.len()].copy_from_slice(buf);
+	}
+
+	fn step(&mut self, _cyc: usize) {
+	}
+
+	fn cycle(&mut self, kind: Cycle, _addr: u16, _t: usize) -> usize {
+	    self.cycles.push(kind);
+	    0
+	}
+
+	fn code_addr(&self, addr: u16) -> Option<u16> {
+	    Some(addr)
+	}
+    }
+
+    fn test_cpu(model: Model) -> Cpu<TestBus> {
+	let mut cpu = Cpu::with_bus(TestBus::new(), model);
+	(cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l) = (0x21, 0, 0x22, 0, 0x20, 0);
+	cpu.sp = 0x3000;
+	cpu.pc = 0x1000;
+	cpu
+    }
+
+    //the machine cycles in Intel's tables, flags are all clear so the
+    //conditions on NZ, NC, PO and P are the ones that are met
+    fn documented(op: u8) -> Vec<Cycle> {
+	use Cycle::*;
+	let taken = (op >> 3) & 1 == 0;
+	match op {
+	    0x01 | 0x11 | 0x21 | 0x31 => vec![Fetch, Read, Read], //LXI
+	    0x02 | 0x12 => vec![Fetch, Write], //STAX
+	    0x0a | 0x1a => vec![Fetch, Read], //LDAX
+	    0x22 => vec![Fetch, Read, Read, Write, Write], //SHLD
+	    0x2a => vec![Fetch, Read, Read, Read, Read], //LHLD
+	    0x32 => vec![Fetch, Read, Read, Write], //STA
+	    0x3a => vec![Fetch, Read, Read, Read], //LDA
+	    0x34..=0x36 => vec![Fetch, Read, Write], //INR M, DCR M, MVI M
+	    0x06..=0x3e if op & 7 == 6 => vec![Fetch, Read], //MVI
+	    0x76 => vec![Fetch], //HLT
+	    0x70..=0x77 => vec![Fetch, Write], //MOV M, r
+	    0x40..=0xbf if op & 7 == 6 => vec![Fetch, Read], //MOV r, M and ALU M
+	    0xc9 | 0xd9 => vec![Fetch, StackRead, StackRead], //RET
+	    0xc0..=0xff if op & 7 == 0 && taken => vec![Fetch, StackRead, StackRead], //Rcc
+	    0xc1 | 0xd1 | 0xe1 | 0xf1 => vec![Fetch, StackRead, StackRead], //POP
+	    0xc5 | 0xd5 | 0xe5 | 0xf5 => vec![Fetch, StackWrite, StackWrite], //PUSH
+	    0xc7..=0xff if op & 7 == 7 => vec![Fetch, StackWrite, StackWrite], //RST
+	    0xc2..=0xfa if op & 7 == 2 => vec![Fetch, Read, Read], //Jcc
+	    0xc3 | 0xcb => vec![Fetch, Read, Read], //JMP
+	    0xcd | 0xdd | 0xed | 0xfd => vec![Fetch, Read, Read, StackWrite, StackWrite], //CALL
+	    0xc4..=0xfc if op & 7 == 4 && taken => vec![Fetch, Read, Read, StackWrite, StackWrite], //Ccc
+	    0xc4..=0xfc if op & 7 == 4 => vec![Fetch, Read, Read],
+	    0xc6..=0xfe if op & 7 == 6 => vec![Fetch, Read], //ALU immediate
+	    0xd3 => vec![Fetch, Read, IoWrite], //OUT
+	    0xdb => vec![Fetch, Read, IoRead], //IN
+	    0xe3 => vec![Fetch, StackRead, StackRead, StackWrite, StackWrite], //XTHL
+	    _ => vec![Fetch],
+	}
+    }
+
+    //and nothing is read or written that doesn't have a cycle of its own
+    #[test]
+    fn memory_cycles() {
+	for op in 0..=255u8 {
+	    let mut cpu = test_cpu(Model::I8080);
+	    cpu.bus.mem[0x1000..0x1003].copy_from_slice(&[op, 0x00, 0x24]);
+	    cpu.step();
+	    let want = documented(op);
+	    let count = |kinds: &[Cycle]| want.iter().filter(|k| kinds.contains(k)).count();
+	    assert_eq!(cpu.bus.cycles, want, "opcode {op:02x}");
+	    assert_eq!(cpu.bus.reads, count(&[Cycle::Fetch, Cycle::Read, Cycle::StackRead]), "opcode {op:02x}");
+	    assert_eq!(cpu.bus.writes, count(&[Cycle::Write, Cycle::StackWrite]), "opcode {op:02x}");
+	    assert_eq!(cpu.bus.io, count(&[Cycle::IoRead, Cycle::IoWrite]), "opcode {op:02x}");
+	}
+    }
+
+    fn stacked(cpu: &Cpu<TestBus>) -> u16 {
+	let sp = cpu.sp as usize;
+	cpu.bus.mem[sp] as u16 | (cpu.bus.mem[sp + 1] as u16) << 8
+    }
+
+    //the instruction after EI always runs before an interrupt is taken
+    #[test]
+    fn ei_delay() {
+	let mut cpu = test_cpu(Model::I8080);
+	cpu.bus.mem[0x1000] = 0xfb; //EI, then NOPs
+	(cpu.bus.irq, cpu.bus.irq_instr) = (true, vec![0xff]);
+	cpu.step();
+	assert_eq!(cpu.pc, 0x1001);
+	cpu.step();
+	assert_eq!(cpu.pc, 0x1002);
+	assert!(cpu.bus.irq);
+	cpu.step();
+	assert_eq!(cpu.pc, 0x38);
+	assert_eq!(stacked(&cpu), 0x1002);
+	assert!(!cpu.bus.irq && !cpu.ime);
+    }
+
+    #[test]
+    fn di_after_ei() {
+	let mut cpu = test_cpu(Model::I8080);
+	cpu.bus.mem[0x1000..0x1002].copy_from_slice(&[0xfb, 0xf3]); //EI, DI
+	(cpu.bus.irq, cpu.bus.irq_instr) = (true, vec![0xff]);
+	for _ in 0..4 {
+	    cpu.step();
+	}
+	assert_eq!(cpu.pc, 0x1004);
+	assert!(cpu.bus.irq && !cpu.ime);
+    }
+
+    //a CALL handed over a byte per INTA, pc isn't moved past anything
+    #[test]
+    fn inta_call() {
+	let mut cpu = test_cpu(Model::I8080);
+	cpu.ime = true;
+	(cpu.bus.irq, cpu.bus.irq_instr) = (true, vec![0xcd, 0x34, 0x12]);
+	assert_eq!(cpu.step(), 17);
+	assert_eq!(cpu.pc, 0x1234);
+	assert_eq!(stacked(&cpu), 0x1000);
+	use Cycle::*;
+	assert_eq!(cpu.bus.cycles, [Inta, Inta, Inta, StackWrite, StackWrite]);
+	assert_eq!(cpu.bus.reads, 0);
+    }
+
+    //taking an interrupt turns them off, another waits for the handler's EI
+    #[test]
+    fn nested_irq() {
+	let mut cpu = test_cpu(Model::I8080);
+	cpu.bus.mem[0x09] = 0xfb; //the RST 1 handler is NOP, EI, NOP
+	cpu.ime = true;
+	(cpu.bus.irq, cpu.bus.irq_instr) = (true, vec![0xcf]);
+	cpu.step();
+	assert_eq!(cpu.pc, 0x08);
+	cpu.bus.irq = true;
+	for pc in [0x09, 0x0a, 0x0b] {
+	    cpu.step();
+	    assert_eq!(cpu.pc, pc);
+	}
+	assert!(cpu.bus.irq);
+	cpu.step();
+	assert_eq!(cpu.pc, 0x08);
+	assert_eq!(stacked(&cpu), 0x0b);
+	assert_eq!(cpu.sp, 0x3000 - 4);
+    }
+
+    //the table has the full time for unconditional branches and the not
+    //taken time for conditional ones. flags are clear, so NZ is met and Z isn't
+    #[test]
+    fn branch_timing() {
+	for (op, states) in [(0xcd, 17), (0xc4, 17), (0xcc, 11), (0xc9, 10), (0xc0, 11), (0xc8, 5),
+			     (0xc3, 10), (0xc2, 10), (0xca, 10), (0xc7, 11)] {
+	    let mut cpu = test_cpu(Model::I8080);
+	    cpu.bus.mem[0x1000..0x1003].copy_from_slice(&[op, 0x00, 0x24]);
+	    assert_eq!(cpu.step(), states, "opcode {op:02x}");
+	}
+    }
+
+    //the decoded handlers against the interpreter. each instruction runs
+    //twice so the second time comes out of the cache
+    #[test]
+    fn predecoded_same() {
+	let mut seed = 1;
+	for model in [Model::I8080, Model::I8085] {
+	    for op in 0..=255u8 {
+		for _ in 0..8 {
+		    let regs: Vec<u8> = (0..12).map(|_| noise(&mut seed)).collect();
+		    let cpus = [false, true].map(|predecode| {
+			let mut cpu = test_cpu(model);
+			cpu.predecode = predecode;
+			(cpu.a, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l) = (regs[0], regs[1], regs[2], regs[3], regs[4], regs[5], regs[6]);
+			cpu.f = PSW::from_bits_truncate(regs[7]);
+			cpu.sp = (regs[8] as u16) << 8 | regs[9] as u16;
+			cpu.bus.mem[0x1000..0x1003].copy_from_slice(&[op, regs[10], regs[11]]);
+			for _ in 0..2 {
+			    cpu.pc = 0x1000;
+			    cpu.step();
+			}
+			cpu
+		    });
+		    let state = |c: &Cpu<TestBus>| ([c.a, c.b, c.c, c.d, c.e, c.h, c.l, c.f.as_u8(), c.masks], c.sp, c.pc,
+						     c.cycles, c.ime, c.ei_pend, c.halted);
+		    assert_eq!(state(&cpus[0]), state(&cpus[1]), "{model:?} opcode {op:02x} from {regs:02x?}");
+		    assert!(cpus[0].bus.mem == cpus[1].bus.mem, "{model:?} opcode {op:02x} from {regs:02x?}");
+		}
+	    }
+	}
+    }
+
+    //a clone starts with the decoded code but writes to it only count
+    //on the side that made them
+    #[test]
+    fn clone_keeps_own_code() {
+	let mut cpu = test_cpu(Model::I8080);
+	cpu.bus.mem[0x1000..0x1002].copy_from_slice(&[0x3e, 0x01]); //mvi a,1
+	cpu.bus.mem[0x2000..0x2003].copy_from_slice(&[0x32, 0x01, 0x10]); //sta 1001
+	cpu.step();
+	let mut copy = cpu.clone();
+	(copy.a, copy.pc) = (7, 0x2000);
+	copy.step();
+	for c in [&mut cpu, &mut copy] {
+	    c.pc = 0x1000;
+	    c.step();
+	}
+	assert_eq!((cpu.a, copy.a), (1, 7));
+    }
+
+    //steps through code at 1000 on an 8085 and adds up the states
+    fn run_8085(code: &[u8], steps: usize) -> (Cpu<TestBus>, usize) {
+	let mut cpu = test_cpu(Model::I8085);
+	cpu.bus.mem[0x1000..0x1000 + code.len()].copy_from_slice(code);
+	let states = (0..steps).map(|_| cpu.step()).sum();
+	(cpu, states)
+    }
+
+    #[test]
+    fn undocumented_8085() {
+	//LXI H,1234  LXI B,0235  DSUB
+	let (cpu, states) = run_8085(&[0x21, 0x34, 0x12, 0x01, 0x35, 0x02, 0x08], 3);
+	assert_eq!((cpu.read_rp(2), cpu.f.as_u8(), states), (0x0fff, 0x14, 30));
+	//LXI H,8003  ARHL
+	let (cpu, states) = run_8085(&[0x21, 0x03, 0x80, 0x10], 2);
+	assert_eq!((cpu.read_rp(2), cpu.f.as_u8(), states), (0xc001, 0x01, 17));
+	//LXI D,4001  STC  RDEL, the top bit changing sets V
+	let (cpu, states) = run_8085(&[0x11, 0x01, 0x40, 0x37, 0x18], 3);
+	assert_eq!((cpu.read_rp(1), cpu.f.as_u8(), states), (0x8003, 0x02, 24));
+	//LDHI 10, then LDSI 02 from sp 3000
+	let (cpu, _) = run_8085(&[0x28, 0x10], 1);
+	assert_eq!(cpu.read_rp(1), 0x2010);
+	let (cpu, _) = run_8085(&[0x38, 0x02], 1);
+	assert_eq!(cpu.read_rp(1), 0x3002);
+	//LXI D,2400  LXI H,BEEF  SHLX  LXI H,0  LHLX
+	let (cpu, states) = run_8085(&[0x11, 0x00, 0x24, 0x21, 0xef, 0xbe, 0xd9, 0x21, 0x00, 0x00, 0xed], 5);
+	assert_eq!((cpu.bus.mem[0x2400], cpu.bus.mem[0x2401], cpu.read_rp(2)), (0xef, 0xbe, 0xbeef));
+	assert_eq!(states, 10 + 10 + 10 + 10 + 10);
+	//LXI B,FFFF  INX B sets K, so JNK falls through and JK goes
+	let (cpu, states) = run_8085(&[0x01, 0xff, 0xff, 0x03, 0xdd, 0x00, 0x20, 0xfd, 0x00, 0x30], 4);
+	assert_eq!((cpu.pc, states), (0x3000, 10 + 6 + 7 + 10));
+	//RSTV only goes to 40 on overflow. MVI A,7F  ADI 01  RSTV
+	let (cpu, states) = run_8085(&[0x3e, 0x7f, 0xc6, 0x01, 0xcb], 3);
+	assert_eq!((cpu.pc, stacked(&cpu), states), (0x40, 0x1005, 7 + 7 + 12));
+	let (cpu, states) = run_8085(&[0xcb], 1);
+	assert_eq!((cpu.pc, states), (0x1001, 6));
+    }
+
+    #[test]
+    fn rim_sim() {
+	//MVI A,0B  SIM  RIM, MSE set so the masks are 011
+	let (mut cpu, _) = run_8085(&[0x3e, 0x0b, 0x30, 0x20], 3);
+	assert_eq!((cpu.masks, cpu.a), (3, 0x03));
+	//pending 6.5 and 5.5, SID and IE all show up
+	(cpu.pins.rst65, cpu.pins.rst55, cpu.pins.sid, cpu.ime) = (true, true, true, true);
+	cpu.pc = 0x1003;
+	cpu.step();
+	assert_eq!(cpu.a, 0x80 | 0x20 | 0x10 | 0x08 | 0x03);
+	//without MSE the masks stay put
+	let (cpu, _) = run_8085(&[0x3e, 0x0b, 0x30, 0x3e, 0x04, 0x30], 4);
+	assert_eq!(cpu.masks, 3);
+    }
+
+    #[test]
+    fn sod() {
+	//MVI A,C0  SIM  MVI A,80  SIM  MVI A,40  SIM
+	let mut cpu = test_cpu(Model::I8085);
+	cpu.bus.mem[0x1000..0x1009].copy_from_slice(&[0x3e, 0xc0, 0x30, 0x3e, 0x80, 0x30, 0x3e, 0x40, 0x30]);
+	let mut sod = Vec::new();
+	for _ in 0..3 {
+	    cpu.step();
+	    cpu.step();
+	    sod.push(cpu.pins.sod);
+	}
+	//SOE has to be set for SOD to be latched
+	assert_eq!(sod, [true, true, false]);
+    }
+
+    #[test]
+    fn rst75_latch() {
+	//MVI A,08  SIM  RIM  EI  NOP  NOP, all unmasked
+	let code = [0x3e, 0x08, 0x30, 0x20, 0xfb, 0x00, 0x00];
+	let (mut cpu, _) = run_8085(&code, 2);
+	//a pulse while interrupts are off is held
+	cpu.pins.rst75 = true;
+	cpu.step();
+	cpu.pins.rst75 = false;
+	assert_eq!(cpu.a, 0x40);
+	cpu.step();
+	cpu.step();
+	assert_eq!(cpu.pc, 0x1006);
+	assert_eq!(cpu.step(), 12);
+	assert_eq!((cpu.pc, cpu.ime, cpu.i75, stacked(&cpu)), (0x3c, false, false, 0x1006));
+
+	//R7.5 in the SIM clears it before it's taken. MVI A,18  SIM  EI  NOP
+	let (mut cpu, _) = run_8085(&[0x3e, 0x08, 0x30], 2);
+	cpu.pins.rst75 = true;
+	cpu.bus.mem[0x1003..0x1008].copy_from_slice(&[0x3e, 0x18, 0x30, 0xfb, 0x00]);
+	for _ in 0..5 {
+	    cpu.step();
+	}
+	assert_eq!((cpu.pc, cpu.i75), (0x1009, false));
+
+	//and masked it waits. the reset masks are all set
+	let (mut cpu, _) = run_8085(&[0xfb, 0x00, 0x00], 1);
+	cpu.pins.rst75 = true;
+	cpu.step();
+	cpu.step();
+	assert_eq!((cpu.pc, cpu.i75), (0x1003, true));
+    }
+
+    #[test]
+    fn trap() {
+	//EI  NOP, with RIM  RIM at the trap vector
+	let (mut cpu, _) = run_8085(&[0xfb, 0x00], 2);
+	cpu.bus.mem[0x24..0x26].copy_from_slice(&[0x20, 0x20]);
+	(cpu.pins.trap, cpu.pins.rst75) = (true, true);
+	(cpu.bus.irq, cpu.bus.irq_instr) = (true, vec![0xff]);
+	//ahead of RST 7.5 and INTR
+	assert_eq!(cpu.step(), 12);
+	assert_eq!((cpu.pc, cpu.ime, stacked(&cpu)), (0x24, false, 0x1002));
+	//the first RIM after it has the IE from before, then it's back to
+	//the real one. 7.5 is latched but masked
+	cpu.step();
+	assert_eq!(cpu.a, 0x40 | 0x08 | 0x07);
+	cpu.step();
+	assert_eq!(cpu.a, 0x40 | 0x07);
+	//holding the pin doesn't trap again
+	assert_eq!(cpu.pc, 0x26);
+    }
+
+    //the 8085's table times, and for the conditional jumps and calls the
+    //states it spends on the bus as well. flags are clear so NZ is taken
+    #[test]
+    fn timing_8085() {
+	use Cycle::*;
+	for (op, states) in [(0x00, 4), (0x41, 4), (0x46, 7), (0x70, 7), (0x03, 6), (0x09, 10), (0x34, 10),
+			     (0xcd, 18), (0xc9, 10), (0xc0, 12), (0xc8, 6), (0xc5, 12), (0xc1, 10), (0xc7, 12),
+			     (0xe3, 16), (0xf9, 6), (0xe9, 6), (0x76, 5), (0x08, 10), (0x10, 7), (0x18, 10),
+			     (0x28, 10), (0x38, 10), (0xd9, 10), (0xed, 10), (0xcb, 6)] {
+	    let (_, n) = run_8085(&[op, 0x00, 0x24], 1);
+	    assert_eq!(n, states, "opcode {op:02x}");
+	}
+	for (op, states, cycles) in [
+	    (0xc2, 10, &[Fetch, Read, Read][..]), //JNZ
+	    (0xca, 7, &[Fetch, Read]), //JZ
+	    (0xdd, 10, &[Fetch, Read, Read]), //JNK
+	    (0xfd, 7, &[Fetch, Read]), //JK
+	    (0xc4, 18, &[Fetch, Read, Read, StackWrite, StackWrite]), //CNZ
+	    (0xcc, 9, &[Fetch, Read]), //CZ
+	] {
+	    for predecode in [false, true] {
+		let mut cpu = test_cpu(Model::I8085);
+		cpu.predecode = predecode;
+		cpu.bus.mem[0x1000..0x1003].copy_from_slice(&[op, 0x00, 0x24]);
+		assert_eq!((cpu.step(), cpu.t), (states, states), "opcode {op:02x}");
+		assert_eq!(cpu.bus.cycles, cycles, "opcode {op:02x}");
+		let next = if states > 9 { 0x2400 } else { 0x1003 };
+		assert_eq!(cpu.pc, next, "opcode {op:02x}");
+	    }
+	}
+    }
+
+    //the bus runs the beam up to t for each video write and then to what
+    //step returns, so no instruction can end short of the states it used
+    #[test]
+    fn states_cover_bus() {
+	for model in [Model::I8080, Model::I8085] {
+	    for op in 0..=255u8 {
+		for flags in [0, 0xff] {
+		    let mut cpu = test_cpu(model);
+		    cpu.f = PSW::from_bits_truncate(flags);
+		    cpu.bus.mem[0x1000..0x1003].copy_from_slice(&[op, 0x00, 0x24]);
+		    let n = cpu.step();
+		    assert!(cpu.t <= n, "{model:?} opcode {op:02x} flags {flags:02x}: {} states, {n} returned", cpu.t);
+		}
+	    }
+	}
+	for prefix in [None, Some(0xcb), Some(0xdd), Some(0xed), Some(0xfd)] {
+	    for op in 0..=255u8 {
+		let mut cpu = test_cpu(Model::Z80);
+		let code = match prefix {
+		    Some(p) => [p, op, 0x01, 0x24],
+		    None => [op, 0x00, 0x24, 0x00],
+		};
+		cpu.bus.mem[0x1000..0x1004].copy_from_slice(&code);
+		let n = cpu.step();
+		assert!(cpu.t <= n, "{prefix:02x?} {op:02x}: {} states, {n} returned", cpu.t);
+	    }
+	}
+    }
+}
+
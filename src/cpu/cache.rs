@@ -0,0 +1,268 @@
+//decoded instructions by address. each entry has the function that runs
+//it with its registers, alu op, condition and immediate already picked
+//out, so the common case skips the memory map for every byte and the
+//decoding for every instruction. entries are checked against a
+//generation for the page the code really lives in, which any write
+//landing there bumps, so code in ram is decoded again after it changes
+use std::sync::Arc;
+
+use super::{Cpu, Model, PSW};
+use crate::bus::Bus;
+
+pub(super) type Handler<B> = fn(&mut Cpu<B>, Arg);
+
+//what the handler needs. x and y are register codes, the alu op or the
+//flags a condition tests and the value they have to have
+#[derive(Clone, Copy, Default)]
+pub(super) struct Arg {
+    op: u8,
+    x: u8,
+    y: u8,
+    w: u16, //the immediate byte or word
+}
+
+pub(super) struct Entry<B: Bus> {
+    gen: u32, //0 is empty
+    page: u8, //where the bus says the code lives
+    pub len: u8,
+    pub cycles: u8, //from the instruction table
+    pub wait: u8, //wait states for fetching all of it
+    pub states: u8, //into the instruction once it's fetched, waits included
+    pub run: Handler<B>,
+    pub arg: Arg,
+}
+
+//derive would want B to be Copy as well
+impl<B: Bus> Clone for Entry<B> {
+    fn clone(&self) -> Self {
+	*self
+    }
+}
+
+impl<B: Bus> Copy for Entry<B> {}
+
+//a clone shares the pages until one side decodes into them, which is
+//what makes copying a cpu for every one of a batch of runs cheap
+#[derive(Clone)]
+pub(super) struct CodeCache<B: Bus> {
+    pages: Vec<Option<Arc<[Entry<B>; 256]>>>, //by pc, only where code has run
+    gen: [u32; 256],
+    live: [bool; 256], //pages with cached code, the only ones writes have to check
+    any: bool,
+}
+
+impl<B: Bus> CodeCache<B> {
+    pub fn new() -> Self {
+	CodeCache {
+	    pages: vec![None; 256],
+	    gen: [1; 256],
+	    live: [false; 256],
+	    any: false,
+	}
+    }
+
+    pub fn get(&self, pc: u16) -> Option<&Entry<B>> {
+	let e = &self.pages[(pc >> 8) as usize].as_ref()?[(pc & 0xff) as usize];
+	if e.gen == self.gen[e.page as usize] {
+	    Some(e)
+	} else {
+	    None
+	}
+    }
+
+    //only if every byte comes from the same page of plain memory
+    fn insert(&mut self, bus: &B, pc: u16, mut e: Entry<B>) {
+	let Some(start) = bus.code_addr(pc) else {
+	    return;
+	};
+	let last = pc.wrapping_add(e.len as u16 - 1);
+	let end = start.wrapping_add(e.len as u16 - 1);
+	if bus.code_addr(last) != Some(end) || start >> 8 != end >> 8 {
+	    return;
+	}
+	e.page = (start >> 8) as u8;
+	e.gen = self.gen[e.page as usize];
+	let page = self.pages[(pc >> 8) as usize].get_or_insert_with(|| Arc::new([Entry::empty(); 256]));
+	Arc::make_mut(page)[(pc & 0xff) as usize] = e;
+	self.live[e.page as usize] = true;
+	self.any = true;
+    }
+
+    pub fn wrote(&mut self, bus: &B, addr: u16) {
+	if !self.any {
+	    return;
+	}
+	if let Some(a) = bus.code_addr(addr) {
+	    let page = (a >> 8) as usize;
+	    if self.live[page] {
+		self.live[page] = false;
+		self.gen[page] = self.gen[page].wrapping_add(1).max(1);
+	    }
+	}
+    }
+
+    //for when memory changed behind the cpu's back
+    pub fn flush(&mut self) {
+	*self = CodeCache::new();
+    }
+}
+
+impl<B: Bus> Entry<B> {
+    fn empty() -> Self {
+	Entry {
+	    gen: 0,
+	    page: 0,
+	    len: 1,
+	    cycles: 0,
+	    wait: 0,
+	    states: 0,
+	    run: Cpu::other,
+	    arg: Arg::default(),
+	}
+    }
+}
+
+//the flags a condition code looks at, and the ones of those it wants set
+fn cond_flags(c: u8) -> (u8, u8) {
+    let mask = [PSW::Z, PSW::C, PSW::P, PSW::S][(c >> 1) as usize].bits();
+    (mask, if c & 1 != 0 { mask } else { 0 })
+}
+
+impl<B: Bus> Cpu<B> {
+    //fetch the instruction at pc and pick its handler, keeping it if the
+    //bus says the bytes are plain memory. wait states over what an entry
+    //holds aren't worth caching, and neither are the 8085's conditional
+    //jumps and calls, which fetch less when they aren't taken
+    pub(super) fn decode(&mut self) -> Entry<B> {
+	let (oldcycles, oldt) = (self.cycles, self.t);
+	let [op, op1, op2] = self.fetch_instr(false);
+	let instr = &self.instr_set[op as usize];
+	let w = (op2 as u16) << 8 | op1 as u16;
+	let (x, y) = ((op >> 3) & 7, op & 7);
+	let rp = (op >> 4) & 3;
+	let (run, x, y): (Handler<B>, u8, u8) = match op {
+	    0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 |
+	    0xcb | 0xd9 | 0xdd | 0xed | 0xfd if self.model == Model::I8085 => (Cpu::other, 0, 0),
+	    0x00 | 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 => (Cpu::nop, 0, 0),
+	    0x76 => (Cpu::other, 0, 0),
+	    0x40..=0x7f => (Cpu::mov, x, y),
+	    0x06..=0x3e if y == 6 => (Cpu::mvi, x, 0),
+	    0x01..=0x31 if op & 0xf == 1 => (Cpu::lxi, rp, 0),
+	    0x03..=0x33 if op & 0xf == 3 => (Cpu::inx_rp, rp, 0),
+	    0x0b..=0x3b if op & 0xf == 0xb => (Cpu::dcx_rp, rp, 0),
+	    0x04..=0x3c if y == 4 => (Cpu::inr_r, x, 0),
+	    0x05..=0x3d if y == 5 => (Cpu::dcr_r, x, 0),
+	    0x80..=0xbf => (Cpu::alu, x, y),
+	    0xc6..=0xfe if y == 6 => (Cpu::alu_imm, x, 0),
+	    0xc1..=0xe1 if op & 0xf == 1 => (Cpu::pop_rp, rp, 0),
+	    0xc5..=0xe5 if op & 0xf == 5 => (Cpu::push_rp, rp, 0),
+	    0xc3 | 0xcb => (Cpu::jmp, 0, 0),
+	    0xcd | 0xdd | 0xed | 0xfd => (Cpu::call_uncond, 0, 0),
+	    0xc9 | 0xd9 => (Cpu::ret_uncond, 0, 0),
+	    0xc0..=0xff if y == 2 => (Cpu::jcc, cond_flags(x).0, cond_flags(x).1),
+	    0xc0..=0xff if y == 4 => (Cpu::ccc, cond_flags(x).0, cond_flags(x).1),
+	    0xc0..=0xff if y == 0 => (Cpu::rcc, cond_flags(x).0, cond_flags(x).1),
+	    _ => (Cpu::other, 0, 0),
+	};
+	let e = Entry {
+	    gen: 0,
+	    page: 0,
+	    len: instr.bytes,
+	    cycles: instr.cycles,
+	    wait: (self.cycles - oldcycles).min(u8::MAX as usize) as u8,
+	    states: (self.t - oldt).min(u8::MAX as usize) as u8,
+	    run,
+	    arg: Arg { op, x, y, w },
+	};
+	if self.cycles - oldcycles < u8::MAX as usize && self.t - oldt < u8::MAX as usize && self.taken_8085(op).is_none() {
+	    self.code.insert(&self.bus, self.pc, e);
+	}
+	e
+    }
+
+    fn taken(&self, a: Arg) -> bool {
+	self.f.as_u8() & a.x == a.y
+    }
+
+    fn nop(&mut self, _: Arg) {}
+
+    //anything without a handler of its own goes through the interpreter
+    fn other(&mut self, a: Arg) {
+	self.exec(a.op, a.w);
+    }
+
+    fn mov(&mut self, a: Arg) {
+	let hlptr = self.read_rp(2);
+	let s = self.read_reg(a.y, hlptr);
+	self.movb(a.x, s, hlptr);
+    }
+
+    fn mvi(&mut self, a: Arg) {
+	let hlptr = self.read_rp(2);
+	self.movb(a.x, a.w as u8, hlptr);
+    }
+
+    fn lxi(&mut self, a: Arg) {
+	self.write_rp(a.x, a.w);
+    }
+
+    fn inx_rp(&mut self, a: Arg) {
+	self.inx(a.x);
+    }
+
+    fn dcx_rp(&mut self, a: Arg) {
+	self.dcx(a.x);
+    }
+
+    fn inr_r(&mut self, a: Arg) {
+	self.inr(a.x);
+    }
+
+    fn dcr_r(&mut self, a: Arg) {
+	self.dcr(a.x);
+    }
+
+    fn alu(&mut self, a: Arg) {
+	let hlptr = self.read_rp(2);
+	let s = self.read_reg(a.y, hlptr);
+	self.aluop(a.x, s);
+    }
+
+    fn alu_imm(&mut self, a: Arg) {
+	self.aluop(a.x, a.w as u8);
+    }
+
+    fn push_rp(&mut self, a: Arg) {
+	let tmp = self.read_rp(a.x);
+	self.push_word(tmp);
+    }
+
+    fn pop_rp(&mut self, a: Arg) {
+	let tmp = self.pop_word();
+	self.write_rp(a.x, tmp);
+    }
+
+    fn jmp(&mut self, a: Arg) {
+	self.pc = a.w;
+    }
+
+    fn jcc(&mut self, a: Arg) {
+	self.jump(self.taken(a), 0, a.w);
+    }
+
+    fn call_uncond(&mut self, a: Arg) {
+	self.call(true, 1, a.w);
+    }
+
+    fn ccc(&mut self, a: Arg) {
+	self.call(self.taken(a), 0, a.w);
+    }
+
+    fn ret_uncond(&mut self, _: Arg) {
+	self.ret(true, 1);
+    }
+
+    fn rcc(&mut self, a: Arg) {
+	self.ret(self.taken(a), 0);
+    }
+}
@@ -0,0 +1,1179 @@
+//the Z80 on top of the shared 8080 registers: the CB, DD, ED and FD
+//pages, IX and IY, the second register set, interrupt modes 0-2 and
+//NMI. flags are Zilog's, F1 is N and F3 and F5 are the undocumented
+//X and Y copies of result bits 3 and 5. timing is counted as it goes,
+//4 states per opcode fetch, 3 per memory access plus the internal ones
+use super::{Cpu, Irq, PSW};
+use crate::bus::{Bus, Cycle};
+
+const FS: u8 = 0x80;
+const FZ: u8 = 0x40;
+const FY: u8 = 0x20;
+const FH: u8 = 0x10;
+const FX: u8 = 0x08;
+const FP: u8 = 0x04;
+const FN: u8 = 0x02;
+const FC: u8 = 0x01;
+
+//sign, zero and the two copied bits of a result
+fn sz53(v: u8) -> u8 {
+    (v & (FS | FY | FX)) | if v == 0 { FZ } else { 0 }
+}
+
+fn sz53p(v: u8) -> u8 {
+    sz53(v) | if v.count_ones() & 1 == 0 { FP } else { 0 }
+}
+
+//what HL means for this instruction, a DD or FD prefix swaps in IX or IY
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Idx {
+    Hl,
+    Ix,
+    Iy,
+}
+
+impl<B: Bus> Cpu<B> {
+    pub(super) fn step_z80(&mut self, irq: Option<Irq>) -> usize {
+	let oldcycles = self.cycles;
+	match irq {
+	    Some(Irq::Nmi) => {
+		self.iff2 = self.ime;
+		self.ime = false;
+		self.refresh();
+		self.idle(5);
+		self.z_push(self.pc);
+		self.pc = 0x66;
+		return self.cycles - oldcycles;
+	    },
+	    Some(Irq::Intr) => {
+		self.ime = false;
+		self.iff2 = false;
+		self.refresh();
+		self.z_interrupt();
+		return self.cycles - oldcycles;
+	    },
+	    _ => {},
+	}
+	let op = self.z_fetch_op();
+	if self.ei_pend {
+	    self.ime = true;
+	    self.iff2 = true;
+	    self.ei_pend = false;
+	}
+	self.z_exec(op, Idx::Hl);
+	self.cycles - oldcycles
+    }
+
+    fn z_interrupt(&mut self) {
+	self.begin_cycle(Cycle::Inta, self.pc);
+	match self.im {
+	    //mode 0 runs what the device puts on the bus like the 8080,
+	    //only RST and CALL are taken since that's all boards ever send
+	    0 => {
+		let op = self.bus.inta(0);
+		self.idle(6);
+		let addr = if op == 0xcd {
+		    let lo = self.bus.inta(1);
+		    let hi = self.bus.inta(2);
+		    self.idle(7);
+		    (hi as u16) << 8 | lo as u16
+		} else {
+		    (op & 0x38) as u16
+		};
+		self.idle(1);
+		self.z_push(self.pc);
+		self.pc = addr;
+	    },
+	    1 => {
+		self.bus.inta(0);
+		self.idle(7);
+		self.z_push(self.pc);
+		self.pc = 0x38;
+	    },
+	    _ => {
+		let v = self.bus.inta(0);
+		self.idle(7);
+		self.z_push(self.pc);
+		let table = (self.i as u16) << 8 | v as u16;
+		self.pc = self.z_rd16(table);
+	    },
+	}
+	self.wz = self.pc;
+    }
+
+    fn flags(&self) -> u8 {
+	self.f.as_u8()
+    }
+
+    fn set_flags(&mut self, f: u8) {
+	self.f = PSW::from_bits_truncate(f);
+    }
+
+    fn refresh(&mut self) {
+	self.r = (self.r & 0x80) | (self.r.wrapping_add(1) & 0x7f);
+    }
+
+    fn idle(&mut self, states: usize) {
+	self.cycles += states;
+	self.t += states;
+    }
+
+    //an M1 cycle, which also counts up the refresh register
+    fn z_fetch_op(&mut self) -> u8 {
+	self.begin_cycle(Cycle::Fetch, self.pc);
+	let op = self.bus.read_byte(self.pc);
+	self.pc = self.pc.wrapping_add(1);
+	self.refresh();
+	self.idle(4);
+	op
+    }
+
+    fn z_rd(&mut self, addr: u16) -> u8 {
+	self.cycles += 3;
+	self.read(Cycle::Read, addr)
+    }
+
+    fn z_wr(&mut self, addr: u16, data: u8) {
+	self.cycles += 3;
+	self.write(Cycle::Write, addr, data);
+    }
+
+    fn z_rd16(&mut self, addr: u16) -> u16 {
+	let lo = self.z_rd(addr);
+	let hi = self.z_rd(addr.wrapping_add(1));
+	(hi as u16) << 8 | lo as u16
+    }
+
+    fn z_wr16(&mut self, addr: u16, data: u16) {
+	self.z_wr(addr, data as u8);
+	self.z_wr(addr.wrapping_add(1), (data >> 8) as u8);
+    }
+
+    fn z_imm(&mut self) -> u8 {
+	let v = self.z_rd(self.pc);
+	self.pc = self.pc.wrapping_add(1);
+	v
+    }
+
+    fn z_imm16(&mut self) -> u16 {
+	let v = self.z_rd16(self.pc);
+	self.pc = self.pc.wrapping_add(2);
+	v
+    }
+
+    fn z_push(&mut self, data: u16) {
+	self.cycles += 6;
+	self.push_word(data);
+    }
+
+    fn z_pop(&mut self) -> u16 {
+	self.cycles += 6;
+	self.pop_word()
+    }
+
+    fn z_in(&mut self, port: u16) -> u8 {
+	self.begin_cycle(Cycle::IoRead, port);
+	self.idle(4);
+	self.bus.read_io_byte(port as u8)
+    }
+
+    fn z_out(&mut self, port: u16, data: u8) {
+	self.begin_cycle(Cycle::IoWrite, port);
+	self.idle(4);
+	self.bus.write_io_byte(port as u8, data);
+    }
+
+    fn af(&self) -> u16 {
+	(self.a as u16) << 8 | self.flags() as u16
+    }
+
+    fn set_af(&mut self, v: u16) {
+	self.a = (v >> 8) as u8;
+	self.set_flags(v as u8);
+    }
+
+    fn hl_idx(&self, idx: Idx) -> u16 {
+	match idx {
+	    Idx::Hl => self.read_rp(2),
+	    Idx::Ix => self.ix,
+	    Idx::Iy => self.iy,
+	}
+    }
+
+    fn set_hl_idx(&mut self, idx: Idx, v: u16) {
+	match idx {
+	    Idx::Hl => self.write_rp(2, v),
+	    Idx::Ix => self.ix = v,
+	    Idx::Iy => self.iy = v,
+	}
+    }
+
+    //BC, DE, HL or SP, with HL swapped for an index register
+    fn rp_idx(&self, rp: u8, idx: Idx) -> u16 {
+	if rp == 2 {
+	    self.hl_idx(idx)
+	} else {
+	    self.read_rp(rp)
+	}
+    }
+
+    fn set_rp_idx(&mut self, rp: u8, idx: Idx, v: u16) {
+	if rp == 2 {
+	    self.set_hl_idx(idx, v);
+	} else {
+	    self.write_rp(rp, v);
+	}
+    }
+
+    //register by its 3 bit code, never 6. H and L become the halves of
+    //IX or IY under a prefix, which is undocumented but used
+    fn reg8(&self, r: u8, idx: Idx) -> u8 {
+	match (r, idx) {
+	    (4, Idx::Ix) => (self.ix >> 8) as u8,
+	    (5, Idx::Ix) => self.ix as u8,
+	    (4, Idx::Iy) => (self.iy >> 8) as u8,
+	    (5, Idx::Iy) => self.iy as u8,
+	    (0, _) => self.b,
+	    (1, _) => self.c,
+	    (2, _) => self.d,
+	    (3, _) => self.e,
+	    (4, _) => self.h,
+	    (5, _) => self.l,
+	    _ => self.a,
+	}
+    }
+
+    fn set_reg8(&mut self, r: u8, idx: Idx, v: u8) {
+	match (r, idx) {
+	    (4, Idx::Ix) => self.ix = (self.ix & 0x00ff) | (v as u16) << 8,
+	    (5, Idx::Ix) => self.ix = (self.ix & 0xff00) | v as u16,
+	    (4, Idx::Iy) => self.iy = (self.iy & 0x00ff) | (v as u16) << 8,
+	    (5, Idx::Iy) => self.iy = (self.iy & 0xff00) | v as u16,
+	    (0, _) => self.b = v,
+	    (1, _) => self.c = v,
+	    (2, _) => self.d = v,
+	    (3, _) => self.e = v,
+	    (4, _) => self.h = v,
+	    (5, _) => self.l = v,
+	    _ => self.a = v,
+	}
+    }
+
+    //the address (HL) stands for, or (IX+d) with the displacement fetched
+    fn mem_addr(&mut self, idx: Idx) -> u16 {
+	if idx == Idx::Hl {
+	    return self.read_rp(2);
+	}
+	let d = self.z_imm() as i8;
+	self.idle(5);
+	let addr = self.hl_idx(idx).wrapping_add(d as u16);
+	self.wz = addr;
+	addr
+    }
+
+    fn z_alu(&mut self, op: u8, v: u8) {
+	let a = self.a;
+	let cf = self.flags() & FC;
+	match op {
+	    0 | 1 => { //ADD, ADC
+		let c = if op == 1 { cf } else { 0 };
+		let r = a as u16 + v as u16 + c as u16;
+		let res = r as u8;
+		let mut f = sz53(res) | ((a ^ v ^ res) & FH);
+		if r > 0xff {
+		    f |= FC;
+		}
+		if (a ^ v ^ 0x80) & (a ^ res) & 0x80 != 0 {
+		    f |= FP;
+		}
+		self.a = res;
+		self.set_flags(f);
+	    },
+	    2 | 3 | 7 => { //SUB, SBC, CP
+		let c = if op == 3 { cf } else { 0 };
+		let r = (a as u16).wrapping_sub(v as u16).wrapping_sub(c as u16);
+		let res = r as u8;
+		let mut f = sz53(res) | FN | ((a ^ v ^ res) & FH);
+		if r > 0xff {
+		    f |= FC;
+		}
+		if (a ^ v) & (a ^ res) & 0x80 != 0 {
+		    f |= FP;
+		}
+		if op == 7 {
+		    f = (f & !(FY | FX)) | (v & (FY | FX)); //CP copies them from the operand
+		} else {
+		    self.a = res;
+		}
+		self.set_flags(f);
+	    },
+	    4 => { //AND
+		self.a &= v;
+		self.set_flags(sz53p(self.a) | FH);
+	    },
+	    5 => { //XOR
+		self.a ^= v;
+		self.set_flags(sz53p(self.a));
+	    },
+	    _ => { //OR
+		self.a |= v;
+		self.set_flags(sz53p(self.a));
+	    },
+	}
+    }
+
+    fn z_inc(&mut self, v: u8) -> u8 {
+	let res = v.wrapping_add(1);
+	let mut f = (self.flags() & FC) | sz53(res);
+	if v & 0xf == 0xf {
+	    f |= FH;
+	}
+	if v == 0x7f {
+	    f |= FP;
+	}
+	self.set_flags(f);
+	res
+    }
+
+    fn z_dec(&mut self, v: u8) -> u8 {
+	let res = v.wrapping_sub(1);
+	let mut f = (self.flags() & FC) | FN | sz53(res);
+	if v & 0xf == 0 {
+	    f |= FH;
+	}
+	if v == 0x80 {
+	    f |= FP;
+	}
+	self.set_flags(f);
+	res
+    }
+
+    fn z_add16(&mut self, a: u16, b: u16) -> u16 {
+	let r = a as u32 + b as u32;
+	let res = r as u16;
+	let mut f = (self.flags() & (FS | FZ | FP)) | ((a ^ b ^ res) >> 8) as u8 & FH | (res >> 8) as u8 & (FY | FX);
+	if r > 0xffff {
+	    f |= FC;
+	}
+	self.set_flags(f);
+	self.wz = a.wrapping_add(1);
+	self.idle(7);
+	res
+    }
+
+    //ADC HL and SBC HL, which unlike ADD set every flag
+    fn z_adc16(&mut self, v: u16, sub: bool) {
+	let hl = self.read_rp(2);
+	let c = (self.flags() & FC) as u32;
+	let r = if sub {
+	    (hl as u32).wrapping_sub(v as u32).wrapping_sub(c)
+	} else {
+	    hl as u32 + v as u32 + c
+	};
+	let res = r as u16;
+	let mut f = (res >> 8) as u8 & (FS | FY | FX) | ((hl ^ v ^ res) >> 8) as u8 & FH;
+	if res == 0 {
+	    f |= FZ;
+	}
+	if r > 0xffff {
+	    f |= FC;
+	}
+	let overflow = if sub {
+	    (hl ^ v) & (hl ^ res) & 0x8000
+	} else {
+	    (hl ^ v ^ 0x8000) & (hl ^ res) & 0x8000
+	};
+	if overflow != 0 {
+	    f |= FP;
+	}
+	if sub {
+	    f |= FN;
+	}
+	self.set_flags(f);
+	self.wz = hl.wrapping_add(1);
+	self.write_rp(2, res);
+	self.idle(7);
+    }
+
+    //the CB page shifts and rotates
+    fn z_rot(&mut self, op: u8, v: u8) -> u8 {
+	let c = self.flags() & FC;
+	let (res, out) = match op {
+	    0 => (v.rotate_left(1), v >> 7), //RLC
+	    1 => (v.rotate_right(1), v & 1), //RRC
+	    2 => ((v << 1) | c, v >> 7), //RL
+	    3 => ((v >> 1) | (c << 7), v & 1), //RR
+	    4 => (v << 1, v >> 7), //SLA
+	    5 => ((v >> 1) | (v & 0x80), v & 1), //SRA
+	    6 => ((v << 1) | 1, v >> 7), //SLL, undocumented
+	    _ => (v >> 1, v & 1), //SRL
+	};
+	self.set_flags(sz53p(res) | out);
+	res
+    }
+
+    //xy is where the copied bits come from, which depends on the operand
+    fn z_bit(&mut self, n: u8, v: u8, xy: u8) {
+	let set = v & (1 << n);
+	let mut f = (self.flags() & FC) | FH | (xy & (FY | FX));
+	if set == 0 {
+	    f |= FZ | FP;
+	}
+	if n == 7 && set != 0 {
+	    f |= FS;
+	}
+	self.set_flags(f);
+    }
+
+    fn z_exec(&mut self, op: u8, idx: Idx) {
+	let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+	let (p, q) = (y >> 1, y & 1);
+	match (x, z) {
+	    (0, 0) => match y {
+		0 => {}, //NOP
+		1 => { //EX AF, AF'
+		    let af = self.af();
+		    self.set_af(self.alt[0]);
+		    self.alt[0] = af;
+		},
+		2 => { //DJNZ
+		    self.idle(1);
+		    let e = self.z_imm() as i8;
+		    self.b = self.b.wrapping_sub(1);
+		    if self.b != 0 {
+			self.idle(5);
+			self.pc = self.pc.wrapping_add(e as u16);
+			self.wz = self.pc;
+		    }
+		},
+		_ => { //JR, JR cc
+		    let e = self.z_imm() as i8;
+		    if y == 3 || self.cond(y - 4) {
+			self.idle(5);
+			self.pc = self.pc.wrapping_add(e as u16);
+			self.wz = self.pc;
+		    }
+		},
+	    },
+	    (0, 1) => {
+		if q == 0 { //LD rr, nn
+		    let v = self.z_imm16();
+		    self.set_rp_idx(p, idx, v);
+		} else { //ADD HL, rr
+		    let v = self.z_add16(self.hl_idx(idx), self.rp_idx(p, idx));
+		    self.set_hl_idx(idx, v);
+		}
+	    },
+	    (0, 2) => match (q, p) {
+		(0, 2) => { //LD (nn), HL
+		    let nn = self.z_imm16();
+		    self.z_wr16(nn, self.hl_idx(idx));
+		    self.wz = nn.wrapping_add(1);
+		},
+		(1, 2) => { //LD HL, (nn)
+		    let nn = self.z_imm16();
+		    let v = self.z_rd16(nn);
+		    self.set_hl_idx(idx, v);
+		    self.wz = nn.wrapping_add(1);
+		},
+		(0, _) => { //LD (BC), A  LD (DE), A  LD (nn), A
+		    let addr = if p == 3 { self.z_imm16() } else { self.read_rp(p) };
+		    self.z_wr(addr, self.a);
+		    self.wz = (self.a as u16) << 8 | (addr.wrapping_add(1) & 0xff);
+		},
+		_ => { //LD A, (BC)  LD A, (DE)  LD A, (nn)
+		    let addr = if p == 3 { self.z_imm16() } else { self.read_rp(p) };
+		    self.a = self.z_rd(addr);
+		    self.wz = addr.wrapping_add(1);
+		},
+	    },
+	    (0, 3) => { //INC rr, DEC rr
+		self.idle(2);
+		let v = self.rp_idx(p, idx);
+		let v = if q == 0 { v.wrapping_add(1) } else { v.wrapping_sub(1) };
+		self.set_rp_idx(p, idx, v);
+	    },
+	    (0, 4) | (0, 5) => { //INC r, DEC r
+		if y == 6 {
+		    let addr = self.mem_addr(idx);
+		    let v = self.z_rd(addr);
+		    self.idle(1);
+		    let v = if z == 4 { self.z_inc(v) } else { self.z_dec(v) };
+		    self.z_wr(addr, v);
+		} else {
+		    let v = self.reg8(y, idx);
+		    let v = if z == 4 { self.z_inc(v) } else { self.z_dec(v) };
+		    self.set_reg8(y, idx, v);
+		}
+	    },
+	    (0, 6) => { //LD r, n
+		if y == 6 && idx != Idx::Hl {
+		    //the displacement and the value both come before the add
+		    let d = self.z_imm() as i8;
+		    let n = self.z_imm();
+		    self.idle(2);
+		    let addr = self.hl_idx(idx).wrapping_add(d as u16);
+		    self.wz = addr;
+		    self.z_wr(addr, n);
+		} else if y == 6 {
+		    let n = self.z_imm();
+		    self.z_wr(self.read_rp(2), n);
+		} else {
+		    let n = self.z_imm();
+		    self.set_reg8(y, idx, n);
+		}
+	    },
+	    (0, _) => self.z_acc_op(y),
+	    (1, _) => {
+		if op == 0x76 { //HALT
+		    self.halted = true;
+		} else if z == 6 { //LD r, (HL)
+		    let addr = self.mem_addr(idx);
+		    let v = self.z_rd(addr);
+		    self.set_reg8(y, Idx::Hl, v);
+		} else if y == 6 { //LD (HL), r
+		    let addr = self.mem_addr(idx);
+		    self.z_wr(addr, self.reg8(z, Idx::Hl));
+		} else { //LD r, r
+		    let v = self.reg8(z, idx);
+		    self.set_reg8(y, idx, v);
+		}
+	    },
+	    (2, _) => { //ALU A, r
+		let v = if z == 6 {
+		    let addr = self.mem_addr(idx);
+		    self.z_rd(addr)
+		} else {
+		    self.reg8(z, idx)
+		};
+		self.z_alu(y, v);
+	    },
+	    (_, 0) => { //RET cc
+		self.idle(1);
+		if self.cond(y) {
+		    self.pc = self.z_pop();
+		    self.wz = self.pc;
+		}
+	    },
+	    (_, 1) => match (q, p) {
+		(0, 3) => { //POP AF
+		    let v = self.z_pop();
+		    self.set_af(v);
+		},
+		(0, _) => { //POP rr
+		    let v = self.z_pop();
+		    self.set_rp_idx(p, idx, v);
+		},
+		(_, 0) => { //RET
+		    self.pc = self.z_pop();
+		    self.wz = self.pc;
+		},
+		(_, 1) => { //EXX
+		    for rp in 0..3 {
+			let v = self.read_rp(rp);
+			self.write_rp(rp, self.alt[rp as usize + 1]);
+			self.alt[rp as usize + 1] = v;
+		    }
+		},
+		(_, 2) => { //JP (HL)
+		    self.pc = self.hl_idx(idx);
+		},
+		_ => { //LD SP, HL
+		    self.idle(2);
+		    self.sp = self.hl_idx(idx);
+		},
+	    },
+	    (_, 2) => { //JP cc, nn
+		let nn = self.z_imm16();
+		self.wz = nn;
+		if self.cond(y) {
+		    self.pc = nn;
+		}
+	    },
+	    (_, 3) => match y {
+		0 => { //JP nn
+		    self.pc = self.z_imm16();
+		    self.wz = self.pc;
+		},
+		1 => self.z_cb(idx),
+		2 => { //OUT (n), A
+		    let n = self.z_imm();
+		    let port = (self.a as u16) << 8 | n as u16;
+		    self.z_out(port, self.a);
+		    self.wz = (self.a as u16) << 8 | (n.wrapping_add(1) as u16);
+		},
+		3 => { //IN A, (n)
+		    let n = self.z_imm();
+		    let port = (self.a as u16) << 8 | n as u16;
+		    self.a = self.z_in(port);
+		    self.wz = port.wrapping_add(1);
+		},
+		4 => { //EX (SP), HL
+		    let sp = self.sp;
+		    let lo = self.read(Cycle::StackRead, sp);
+		    let hi = self.read(Cycle::StackRead, sp.wrapping_add(1));
+		    self.idle(1);
+		    let v = self.hl_idx(idx);
+		    self.write(Cycle::StackWrite, sp.wrapping_add(1), (v >> 8) as u8);
+		    self.write(Cycle::StackWrite, sp, v as u8);
+		    self.cycles += 12;
+		    self.idle(2);
+		    let v = (hi as u16) << 8 | lo as u16;
+		    self.set_hl_idx(idx, v);
+		    self.wz = v;
+		},
+		5 => { //EX DE, HL, never IX or IY
+		    let de = self.read_rp(1);
+		    let hl = self.read_rp(2);
+		    self.write_rp(1, hl);
+		    self.write_rp(2, de);
+		},
+		6 => { //DI
+		    self.ime = false;
+		    self.iff2 = false;
+		    self.ei_pend = false;
+		},
+		_ => { //EI
+		    self.ei_pend = true;
+		},
+	    },
+	    (_, 4) => { //CALL cc, nn
+		let nn = self.z_imm16();
+		self.wz = nn;
+		if self.cond(y) {
+		    self.idle(1);
+		    self.z_push(self.pc);
+		    self.pc = nn;
+		}
+	    },
+	    (_, 5) => match (q, p) {
+		(0, _) => { //PUSH
+		    self.idle(1);
+		    let v = if p == 3 { self.af() } else { self.rp_idx(p, idx) };
+		    self.z_push(v);
+		},
+		(_, 0) => { //CALL nn
+		    let nn = self.z_imm16();
+		    self.wz = nn;
+		    self.idle(1);
+		    self.z_push(self.pc);
+		    self.pc = nn;
+		},
+		(_, 2) => self.z_ed(),
+		//DD and FD, another prefix just replaces the last one
+		(_, p) => {
+		    let op = self.z_fetch_op();
+		    self.z_exec(op, if p == 1 { Idx::Ix } else { Idx::Iy });
+		},
+	    },
+	    (_, 6) => { //ALU A, n
+		let n = self.z_imm();
+		self.z_alu(y, n);
+	    },
+	    _ => { //RST
+		self.idle(1);
+		self.z_push(self.pc);
+		self.pc = (y as u16) << 3;
+		self.wz = self.pc;
+	    },
+	}
+    }
+
+    //the accumulator and flag ops in the first quarter of the page
+    fn z_acc_op(&mut self, y: u8) {
+	let f = self.flags();
+	let keep = f & (FS | FZ | FP);
+	match y {
+	    0 => { //RLCA
+		self.a = self.a.rotate_left(1);
+		self.set_flags(keep | (self.a & (FY | FX)) | (self.a & 1));
+	    },
+	    1 => { //RRCA
+		let c = self.a & 1;
+		self.a = self.a.rotate_right(1);
+		self.set_flags(keep | (self.a & (FY | FX)) | c);
+	    },
+	    2 => { //RLA
+		let c = self.a >> 7;
+		self.a = (self.a << 1) | (f & FC);
+		self.set_flags(keep | (self.a & (FY | FX)) | c);
+	    },
+	    3 => { //RRA
+		let c = self.a & 1;
+		self.a = (self.a >> 1) | ((f & FC) << 7);
+		self.set_flags(keep | (self.a & (FY | FX)) | c);
+	    },
+	    4 => { //DAA
+		let a = self.a;
+		let mut diff = 0;
+		let mut c = f & FC;
+		if f & FH != 0 || a & 0xf > 9 {
+		    diff = 0x06;
+		}
+		if c != 0 || a > 0x99 {
+		    diff |= 0x60;
+		    c = FC;
+		}
+		let (res, h) = if f & FN != 0 {
+		    (a.wrapping_sub(diff), f & FH != 0 && a & 0xf < 6)
+		} else {
+		    (a.wrapping_add(diff), a & 0xf > 9)
+		};
+		self.a = res;
+		self.set_flags(sz53p(res) | (f & FN) | c | if h { FH } else { 0 });
+	    },
+	    5 => { //CPL
+		self.a = !self.a;
+		self.set_flags((f & (FS | FZ | FP | FC)) | FH | FN | (self.a & (FY | FX)));
+	    },
+	    6 => { //SCF
+		self.set_flags(keep | (self.a & (FY | FX)) | FC);
+	    },
+	    _ => { //CCF, H gets the old carry
+		let hc = if f & FC != 0 { FH } else { FC };
+		self.set_flags(keep | (self.a & (FY | FX)) | hc);
+	    },
+	}
+    }
+
+    fn z_cb(&mut self, idx: Idx) {
+	if idx != Idx::Hl {
+	    //DDCB d op, the displacement comes first and the last byte is
+	    //read without an M1. anything but BIT also copies the result to
+	    //the register in the low bits, which is undocumented
+	    let d = self.z_imm() as i8;
+	    let op = self.z_imm();
+	    self.idle(2);
+	    let addr = self.hl_idx(idx).wrapping_add(d as u16);
+	    self.wz = addr;
+	    let v = self.z_rd(addr);
+	    self.idle(1);
+	    let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+	    let res = match x {
+		0 => self.z_rot(y, v),
+		1 => {
+		    self.z_bit(y, v, (addr >> 8) as u8);
+		    return;
+		},
+		2 => v & !(1 << y),
+		_ => v | (1 << y),
+	    };
+	    self.z_wr(addr, res);
+	    if z != 6 {
+		self.set_reg8(z, Idx::Hl, res);
+	    }
+	    return;
+	}
+	let op = self.z_fetch_op();
+	let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+	if z == 6 {
+	    let addr = self.read_rp(2);
+	    let v = self.z_rd(addr);
+	    self.idle(1);
+	    let res = match x {
+		0 => self.z_rot(y, v),
+		1 => {
+		    self.z_bit(y, v, (self.wz >> 8) as u8);
+		    return;
+		},
+		2 => v & !(1 << y),
+		_ => v | (1 << y),
+	    };
+	    self.z_wr(addr, res);
+	} else {
+	    let v = self.reg8(z, Idx::Hl);
+	    let res = match x {
+		0 => self.z_rot(y, v),
+		1 => {
+		    self.z_bit(y, v, v);
+		    return;
+		},
+		2 => v & !(1 << y),
+		_ => v | (1 << y),
+	    };
+	    self.set_reg8(z, Idx::Hl, res);
+	}
+    }
+
+    fn z_ed(&mut self) {
+	let op = self.z_fetch_op();
+	let (x, y, z) = (op >> 6, (op >> 3) & 7, op & 7);
+	let (p, q) = (y >> 1, y & 1);
+	match (x, z) {
+	    (1, 0) => { //IN r, (C), 6 only sets the flags
+		let bc = self.read_rp(0);
+		let v = self.z_in(bc);
+		self.wz = bc.wrapping_add(1);
+		if y != 6 {
+		    self.set_reg8(y, Idx::Hl, v);
+		}
+		self.set_flags((self.flags() & FC) | sz53p(v));
+	    },
+	    (1, 1) => { //OUT (C), r, 6 sends 0
+		let bc = self.read_rp(0);
+		let v = if y == 6 { 0 } else { self.reg8(y, Idx::Hl) };
+		self.z_out(bc, v);
+		self.wz = bc.wrapping_add(1);
+	    },
+	    (1, 2) => { //SBC HL, rr  ADC HL, rr
+		let v = self.read_rp(p);
+		self.z_adc16(v, q == 0);
+	    },
+	    (1, 3) => { //LD (nn), rr  LD rr, (nn)
+		let nn = self.z_imm16();
+		if q == 0 {
+		    self.z_wr16(nn, self.read_rp(p));
+		} else {
+		    let v = self.z_rd16(nn);
+		    self.write_rp(p, v);
+		}
+		self.wz = nn.wrapping_add(1);
+	    },
+	    (1, 4) => { //NEG
+		let v = self.a;
+		self.a = 0;
+		self.z_alu(2, v);
+	    },
+	    (1, 5) => { //RETN, RETI
+		self.pc = self.z_pop();
+		self.wz = self.pc;
+		self.ime = self.iff2;
+	    },
+	    (1, 6) => { //IM
+		self.im = [0, 0, 1, 2][(y & 3) as usize];
+	    },
+	    (1, _) => match y {
+		0 => { //LD I, A
+		    self.idle(1);
+		    self.i = self.a;
+		},
+		1 => { //LD R, A
+		    self.idle(1);
+		    self.r = self.a;
+		},
+		2 | 3 => { //LD A, I  LD A, R
+		    self.idle(1);
+		    self.a = if y == 2 { self.i } else { self.r };
+		    let mut f = (self.flags() & FC) | sz53(self.a);
+		    if self.iff2 {
+			f |= FP;
+		    }
+		    self.set_flags(f);
+		},
+		4 | 5 => { //RRD, RLD
+		    let addr = self.read_rp(2);
+		    let v = self.z_rd(addr);
+		    self.idle(4);
+		    let (m, a) = if y == 4 {
+			((self.a << 4) | (v >> 4), (self.a & 0xf0) | (v & 0x0f))
+		    } else {
+			((v << 4) | (self.a & 0x0f), (self.a & 0xf0) | (v >> 4))
+		    };
+		    self.z_wr(addr, m);
+		    self.a = a;
+		    self.set_flags((self.flags() & FC) | sz53p(a));
+		    self.wz = addr.wrapping_add(1);
+		},
+		_ => {},
+	    },
+	    (2, 0..=3) if y >= 4 => self.z_block(y, z),
+	    _ => {}, //the rest of the page does nothing
+	}
+    }
+
+    //LDI, CPI, INI, OUTI, the D versions going down and the R versions repeating
+    fn z_block(&mut self, y: u8, z: u8) {
+	let step = if y & 1 == 0 { 1 } else { 0xffff };
+	let repeat = y >= 6;
+	let hl = self.read_rp(2);
+	self.write_rp(2, hl.wrapping_add(step));
+	let again = match z {
+	    0 => {
+		let de = self.read_rp(1);
+		let v = self.z_rd(hl);
+		self.z_wr(de, v);
+		self.idle(2);
+		self.write_rp(1, de.wrapping_add(step));
+		let bc = self.read_rp(0).wrapping_sub(1);
+		self.write_rp(0, bc);
+		let n = v.wrapping_add(self.a);
+		let mut f = (self.flags() & (FS | FZ | FC)) | (n & FX) | ((n & 0x02) << 4);
+		if bc != 0 {
+		    f |= FP;
+		}
+		self.set_flags(f);
+		bc != 0
+	    },
+	    1 => {
+		let v = self.z_rd(hl);
+		self.idle(5);
+		let res = self.a.wrapping_sub(v);
+		let bc = self.read_rp(0).wrapping_sub(1);
+		self.write_rp(0, bc);
+		let h = (self.a ^ v ^ res) & FH;
+		let n = res.wrapping_sub((h != 0) as u8);
+		let mut f = (self.flags() & FC) | FN | h | (res & FS) | (n & FX) | ((n & 0x02) << 4);
+		if res == 0 {
+		    f |= FZ;
+		}
+		if bc != 0 {
+		    f |= FP;
+		}
+		self.set_flags(f);
+		self.wz = self.wz.wrapping_add(step);
+		bc != 0 && res != 0
+	    },
+	    2 => {
+		self.idle(1);
+		let bc = self.read_rp(0);
+		let v = self.z_in(bc);
+		self.z_wr(hl, v);
+		self.wz = bc.wrapping_add(step);
+		self.b = self.b.wrapping_sub(1);
+		self.set_flags(sz53(self.b) | FN | (self.flags() & FC));
+		self.b != 0
+	    },
+	    _ => {
+		self.idle(1);
+		let v = self.z_rd(hl);
+		self.b = self.b.wrapping_sub(1);
+		let bc = self.read_rp(0);
+		self.z_out(bc, v);
+		self.wz = bc.wrapping_add(step);
+		self.set_flags(sz53(self.b) | FN | (self.flags() & FC));
+		self.b != 0
+	    },
+	};
+	if repeat && again {
+	    self.idle(5);
+	    self.pc = self.pc.wrapping_sub(2);
+	    self.wz = self.pc.wrapping_add(1);
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::CpmBus;
+    use crate::cpu::Model;
+    use crate::testutil::noise;
+
+    fn z80(code: &[u8]) -> Cpu<CpmBus> {
+	let mut cpu = Cpu::with_bus(CpmBus::new(), Model::Z80);
+	cpu.bus.load_bin(0x100, code);
+	cpu.pc = 0x100;
+	cpu.sp = 0xf000;
+	cpu
+    }
+
+    //until pc runs off the end of the code, returning the states taken
+    fn run(code: &[u8]) -> (Cpu<CpmBus>, usize) {
+	let mut cpu = z80(code);
+	let mut states = 0;
+	while cpu.pc != 0x100 + code.len() as u16 {
+	    states += cpu.step();
+	}
+	(cpu, states)
+    }
+
+    fn mem(cpu: &mut Cpu<CpmBus>, addr: u16) -> u8 {
+	cpu.bus.read_byte(addr)
+    }
+
+    //results and flags worked out by hand from Zilog's descriptions,
+    //including the undocumented X and Y bits
+    #[test]
+    fn alu_flags() {
+	for (code, a, f) in [
+	    (&[0x3e, 0x7f, 0xc6, 0x01][..], 0x80, 0x94), //LD A,7F  ADD A,1: S H V
+	    (&[0x3e, 0x00, 0xd6, 0x01], 0xff, 0xbb), //SUB 1: S Y H X N C
+	    (&[0x3e, 0x40, 0xfe, 0x28], 0x40, 0x3a), //CP 28: X and Y from the operand
+	    (&[0x3e, 0x15, 0xd6, 0x06, 0x27], 0x09, 0x0e), //15-06, DAA
+	    (&[0x3e, 0x80, 0xed, 0x44], 0x80, 0x87), //NEG of 80 overflows
+	    (&[0x3e, 0x80, 0xcb, 0x7f], 0x80, 0x90), //BIT 7,A
+	    (&[0x3e, 0x81, 0xcb, 0x37], 0x03, 0x05), //SLL A, undocumented
+	    (&[0x3e, 0x0f, 0x37, 0x3f], 0x0f, 0x18), //SCF  CCF: H gets the old carry
+	] {
+	    let (cpu, _) = run(code);
+	    assert_eq!((cpu.a, cpu.flags()), (a, f), "{code:02x?}");
+	}
+    }
+
+    #[test]
+    fn sbc_hl() {
+	//LD HL,8000  LD DE,1  OR A  SBC HL,DE
+	let (cpu, states) = run(&[0x21, 0x00, 0x80, 0x11, 0x01, 0x00, 0xb7, 0xed, 0x52]);
+	assert_eq!(cpu.read_rp(2), 0x7fff);
+	assert_eq!(cpu.flags(), 0x3e);
+	assert_eq!(states, 10 + 10 + 4 + 15);
+    }
+
+    #[test]
+    fn djnz() {
+	//LD B,3  loop: INC A  DJNZ loop
+	let (cpu, states) = run(&[0x06, 0x03, 0x3c, 0x10, 0xfd]);
+	assert_eq!((cpu.a, cpu.b), (3, 0));
+	assert_eq!(states, 7 + 3 * 4 + 2 * 13 + 8);
+    }
+
+    #[test]
+    fn block_ops() {
+	//LD HL,200  LD DE,300  LD BC,4  LDIR
+	let mut cpu = z80(&[0x21, 0x00, 0x02, 0x11, 0x00, 0x03, 0x01, 0x04, 0x00, 0xed, 0xb0]);
+	cpu.bus.load_bin(0x200, &[1, 2, 3, 4, 5]);
+	let mut states = 0;
+	while cpu.pc != 0x10b {
+	    states += cpu.step();
+	}
+	assert_eq!((cpu.read_rp(2), cpu.read_rp(1), cpu.read_rp(0)), (0x204, 0x304, 0));
+	assert_eq!((mem(&mut cpu, 0x303), mem(&mut cpu, 0x304)), (4, 0));
+	assert_eq!(cpu.flags() & FP, 0);
+	assert_eq!(states, 30 + 3 * 21 + 16);
+
+	//LD HL,200  LD BC,10  LD A,3  CPIR, stops on the match
+	let mut cpu = z80(&[0x21, 0x00, 0x02, 0x01, 0x0a, 0x00, 0x3e, 0x03, 0xed, 0xb1]);
+	cpu.bus.load_bin(0x200, &[1, 2, 3, 4]);
+	while cpu.pc != 0x10a {
+	    cpu.step();
+	}
+	assert_eq!((cpu.read_rp(2), cpu.read_rp(0)), (0x203, 7));
+	assert_eq!(cpu.flags() & (FZ | FP | FN), FZ | FP | FN);
+    }
+
+    #[test]
+    fn rld() {
+	//LD HL,200  LD A,12  RLD
+	let mut cpu = z80(&[0x21, 0x00, 0x02, 0x3e, 0x12, 0xed, 0x6f]);
+	cpu.bus.load_bin(0x200, &[0x34]);
+	while cpu.pc != 0x107 {
+	    cpu.step();
+	}
+	assert_eq!((cpu.a, mem(&mut cpu, 0x200)), (0x13, 0x42));
+    }
+
+    #[test]
+    fn index_registers() {
+	//LD IX,200  LD (IX+5),42  LD A,(IX+5)  INC (IX-1)  LD IXH,12  LD B,IXH
+	let (mut cpu, states) = run(&[0xdd, 0x21, 0x00, 0x02, 0xdd, 0x36, 0x05, 0x42, 0xdd, 0x7e, 0x05,
+				      0xdd, 0x34, 0xff, 0xdd, 0x26, 0x12, 0xdd, 0x44]);
+	assert_eq!((cpu.a, cpu.b, cpu.ix), (0x42, 0x12, 0x1200));
+	assert_eq!((mem(&mut cpu, 0x205), mem(&mut cpu, 0x1ff)), (0x42, 1));
+	assert_eq!(states, 14 + 19 + 19 + 23 + 11 + 8);
+
+	//LD IY,200  RLC (IY+2),B copies the result to B as well
+	let mut cpu = z80(&[0xfd, 0x21, 0x00, 0x02, 0xfd, 0xcb, 0x02, 0x00]);
+	cpu.bus.load_bin(0x202, &[0x81]);
+	while cpu.pc != 0x108 {
+	    cpu.step();
+	}
+	assert_eq!((mem(&mut cpu, 0x202), cpu.b), (0x03, 0x03));
+	assert_eq!(cpu.flags() & FC, FC);
+    }
+
+    #[test]
+    fn exchanges() {
+	//LD A,1  EX AF,AF'  LD A,2  EX AF,AF'  LD BC,1234  EXX  LD BC,5678  EXX
+	let (cpu, _) = run(&[0x3e, 0x01, 0x08, 0x3e, 0x02, 0x08,
+			     0x01, 0x34, 0x12, 0xd9, 0x01, 0x78, 0x56, 0xd9]);
+	assert_eq!((cpu.a, cpu.alt[0] >> 8), (1, 2));
+	assert_eq!((cpu.read_rp(0), cpu.alt[1]), (0x1234, 0x5678));
+    }
+
+    #[test]
+    fn interrupts() {
+	//LD A,30  LD I,A  IM 2  EI  NOP, the table entry at 3010 says 400
+	let mut cpu = z80(&[0x3e, 0x30, 0xed, 0x47, 0xed, 0x5e, 0xfb, 0x00]);
+	cpu.bus.load_bin(0x3010, &[0x00, 0x04]);
+	(cpu.bus.irq, cpu.bus.irq_vec) = (true, 0x10);
+	for _ in 0..5 {
+	    cpu.step();
+	}
+	assert_eq!(cpu.pc, 0x108);
+	assert_eq!(cpu.step(), 19);
+	assert_eq!((cpu.pc, cpu.ime), (0x400, false));
+	assert_eq!(cpu.pop_word(), 0x108);
+
+	//NMI keeps the old interrupt enable for RETN to put back
+	let mut cpu = z80(&[0xfb, 0x00]);
+	cpu.bus.load_bin(0x66, &[0xed, 0x45]);
+	cpu.step();
+	cpu.step();
+	cpu.pins.nmi = true;
+	assert_eq!(cpu.step(), 11);
+	assert_eq!((cpu.pc, cpu.ime, cpu.iff2), (0x66, false, true));
+	cpu.step();
+	assert_eq!((cpu.pc, cpu.ime), (0x102, true));
+	//it's edge triggered
+	cpu.step();
+	assert_eq!(cpu.pc, 0x103);
+    }
+
+    //the 8080 instructions the Z80 kept should do the same to registers
+    //and memory. flags are only compared where the two chips agree, S Z
+    //and C everywhere and P after the logic ops where it's parity on both
+    #[test]
+    fn same_as_8080() {
+	let mut seed = 7;
+	for op in 0..=255u8 {
+	    let z80_only = matches!(op, 0x08 | 0x10 | 0x18 | 0x20 | 0x28 | 0x30 | 0x38 |
+				    0xcb | 0xd9 | 0xdd | 0xed | 0xfd);
+	    //DAA differs after a subtract, and io could reach the console
+	    if z80_only || matches!(op, 0x27 | 0xd3 | 0xdb) {
+		continue;
+	    }
+	    let logic = matches!(op, 0xa0..=0xb7 | 0xe6 | 0xee | 0xf6);
+	    let mask = FS | FZ | FC | if logic { FP } else { 0 };
+	    for _ in 0..20 {
+		let mut cpus = [Model::I8080, Model::Z80].map(|m| Cpu::with_bus(CpmBus::new(), m));
+		let regs: Vec<u8> = (0..16).map(|_| noise(&mut seed)).collect();
+		for cpu in cpus.iter_mut() {
+		    (cpu.a, cpu.b, cpu.c, cpu.d, cpu.e, cpu.h, cpu.l) = (regs[0], regs[1], regs[2], regs[3], regs[4], regs[5], regs[6]);
+		    cpu.f = PSW::from_bits_truncate(regs[7]);
+		    cpu.sp = (regs[8] as u16) << 8 | regs[9] as u16;
+		    cpu.pc = 0x8000 | (regs[10] as u16) << 4;
+		    for (n, r) in [0x00, regs[11], regs[12]].iter().enumerate() {
+			cpu.bus.write_byte(cpu.pc + n as u16, if n == 0 { op } else { *r });
+		    }
+		    //and something other than 0 behind every pointer
+		    for (i, addr) in [cpu.read_rp(0), cpu.read_rp(1), cpu.read_rp(2), cpu.sp, cpu.sp.wrapping_add(1)]
+			.into_iter().enumerate() {
+			if !(cpu.pc..cpu.pc + 3).contains(&addr) {
+			    cpu.bus.write_byte(addr, regs[13 + i % 3]);
+			}
+		    }
+		    cpu.step();
+		}
+		let [i, z] = &mut cpus;
+		let state = |c: &Cpu<CpmBus>| (c.a, c.b, c.c, c.d, c.e, c.h, c.l, c.sp, c.pc, c.halted, c.ime || c.ei_pend);
+		assert_eq!(state(i), state(z), "opcode {op:02x} from {regs:02x?}");
+		assert_eq!(i.f.as_u8() & mask, z.flags() & mask, "opcode {op:02x} from {regs:02x?}");
+		for addr in 0..=0xffff {
+		    assert_eq!(i.bus.read_byte(addr), z.bus.read_byte(addr), "opcode {op:02x} at {addr:04x}");
+		}
+	    }
+	}
+    }
+
+    //the exercisers aren't in the tree, point ZEXDOC and/or ZEXALL at
+    //the .com files and run with
+    //    cargo test --release -- --ignored zex
+    //a full run takes a few minutes each
+    #[test]
+    #[ignore]
+    fn zex() {
+	let progs: Vec<String> = ["ZEXDOC", "ZEXALL"].iter().filter_map(|v| std::env::var(v).ok()).collect();
+	assert!(!progs.is_empty(), "set ZEXDOC or ZEXALL to the exerciser's path");
+	for path in progs {
+	    let prog = std::fs::read(&path).unwrap_or_else(|e| panic!("{path}: {e}"));
+	    let mut cpu = Cpu::with_bus(CpmBus::new(), Model::Z80);
+	    cpu.bus.load_com(&prog);
+	    cpu.pc = 0x100;
+	    while cpu.pc != 0 {
+		cpu.step();
+	    }
+	    let out = &cpu.bus.console;
+	    assert!(out.contains("Tests complete") && !out.contains("ERROR"), "{path}:\n{out}");
+	}
+    }
+}
@@ -0,0 +1,477 @@
+use crate::rom::RomFile;
+
+//everything that differs between games on the midway/taito 8080 board:
+//which chips go where, what's on each i/o port, and what the switches do.
+//only the invaders set has known checksums here, the others are matched
+//by file name and their port layouts follow MAME without having been
+//checked against the real roms
+pub struct Driver {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub roms: &'static [RomFile],
+    pub map: &'static [Region],
+    pub addr_mask: u16, //address lines the board decodes, the rest mirror
+    pub reads: &'static [(u8, PortRead)],
+    pub writes: &'static [(u8, PortWrite)],
+    pub inputs: &'static [InputPort],
+    pub dips: &'static [Dip],
+    pub dip_default: u8,
+    //sample to play for each sound bit
+    pub samples: &'static [&'static str],
+    pub color: Option<&'static ColorProm>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mem {
+    Rom, //indexed by address, the same as the offsets in roms
+    Ram,
+    Vram,
+    Mirror(u16), //same as the address this many bytes lower
+}
+
+//a range of the address space, end inclusive. anything not covered
+//by a driver's map is unmapped
+pub struct Region {
+    pub start: u16,
+    pub end: u16,
+    pub mem: Mem,
+    pub wait: usize, //wait states for each access
+}
+
+//taito's colour boards look up a colour for each 8x8 cell of the screen
+//in a prom. latches on the sound ports pick one of two maps (for the two
+//players) or turn the whole screen red
+pub struct ColorProm {
+    pub proms: &'static [RomFile], //0x400 bytes per map
+    pub bank: (u8, u8), //port and bit
+    pub red: (u8, u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PortRead {
+    Inputs(usize), //index into Driver::inputs
+    Shifter,
+    ShifterReversed,
+    Watchdog,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PortWrite {
+    ShiftAmount,
+    ShiftData,
+    Sound(usize, usize), //first sample and how many bits, bit n triggers first + n
+    Watchdog,
+    Ignore, //lamps and such
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Input {
+    Coin,
+    Tilt,
+    P1Start,
+    P1Fire,
+    P1Left,
+    P1Right,
+    P1Up,
+    P1Down,
+    P2Start,
+    P2Fire,
+    P2Left,
+    P2Right,
+    P2Up,
+    P2Down,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    Input(Input),
+    Dip(u8), //bit of the dip switch byte
+    High,
+}
+
+pub struct InputPort {
+    pub bits: &'static [(u8, Source)], //bit number, where it comes from
+    pub active_low: u8,
+}
+
+//a group of switches, mask is over the dip switch byte
+pub struct Dip {
+    pub name: &'static str,
+    pub mask: u8,
+}
+
+use Input::*;
+use Source::{Input as In, Dip as Sw, High};
+
+const MIDWAY_MAP: [Region; 3] = [
+    Region { start: 0x0000, end: 0x1fff, mem: Mem::Rom, wait: 0 },
+    Region { start: 0x2000, end: 0x23ff, mem: Mem::Ram, wait: 0 },
+    Region { start: 0x2400, end: 0x3fff, mem: Mem::Vram, wait: 0 },
+];
+
+//taito boards have a second bank of rom above the video ram and
+//decode one more address line, so ram shows up again above that
+const TAITO_MAP: [Region; 5] = [
+    Region { start: 0x0000, end: 0x1fff, mem: Mem::Rom, wait: 0 },
+    Region { start: 0x2000, end: 0x23ff, mem: Mem::Ram, wait: 0 },
+    Region { start: 0x2400, end: 0x3fff, mem: Mem::Vram, wait: 0 },
+    Region { start: 0x4000, end: 0x5fff, mem: Mem::Rom, wait: 0 },
+    Region { start: 0x6000, end: 0x7fff, mem: Mem::Mirror(0x4000), wait: 0 },
+];
+
+const MIDWAY_READS: [(u8, PortRead); 4] = [
+    (0, PortRead::Inputs(0)),
+    (1, PortRead::Inputs(1)),
+    (2, PortRead::Inputs(2)),
+    (3, PortRead::Shifter),
+];
+
+const MIDWAY_WRITES: [(u8, PortWrite); 5] = [
+    (2, PortWrite::ShiftAmount),
+    (3, PortWrite::Sound(0, 4)),
+    (4, PortWrite::ShiftData),
+    (5, PortWrite::Sound(4, 5)),
+    (6, PortWrite::Watchdog),
+];
+
+const INVADERS_INPUTS: [InputPort; 3] = [
+    InputPort { bits: &[(0, Sw(4)), (4, High), (5, High), (6, High)], active_low: 0 },
+    InputPort {
+	bits: &[(0, In(Coin)), (1, In(P2Start)), (2, In(P1Start)), (3, High),
+		(4, In(P1Fire)), (5, In(P1Left)), (6, In(P1Right))],
+	active_low: 0,
+    },
+    InputPort {
+	bits: &[(0, Sw(3)), (1, Sw(5)), (2, In(Tilt)), (3, Sw(6)),
+		(4, In(P2Fire)), (5, In(P2Left)), (6, In(P2Right)), (7, Sw(7))],
+	active_low: 0,
+    },
+];
+
+//coin is active low on these, lives are on the bottom two switches
+const TAITO_INPUTS: [InputPort; 3] = [
+    InputPort { bits: &[], active_low: 0xff },
+    InputPort {
+	bits: &[(0, In(Coin)), (1, In(P2Start)), (2, In(P1Start)),
+		(4, In(P1Fire)), (5, In(P1Left)), (6, In(P1Right))],
+	active_low: 0x09,
+    },
+    InputPort {
+	bits: &[(0, Sw(0)), (1, Sw(1)), (2, In(Tilt)), (3, Sw(3)),
+		(4, In(P2Fire)), (5, In(P2Left)), (6, In(P2Right)), (7, Sw(7))],
+	active_low: 0,
+    },
+];
+
+const INVADERS_DIPS: [Dip; 4] = [
+    Dip { name: "lives (3, 4, 5, 6)", mask: 0x28 },
+    Dip { name: "bonus life at 1000 instead of 1500", mask: 0x40 },
+    Dip { name: "hide coin info", mask: 0x80 },
+    Dip { name: "unused", mask: 0x10 },
+];
+
+const INVADERS_SAMPLES: [&str; 9] = [
+    "sfx/0.wav", "sfx/1.wav", "sfx/3.wav", "sfx/4.wav", "sfx/5.wav",
+    "sfx/6.wav", "sfx/7.wav", "sfx/8.wav", "sfx/9.wav",
+];
+
+const LRESCUE_DIPS: [Dip; 3] = [
+    Dip { name: "lives (3, 4, 5, 6)", mask: 0x03 },
+    Dip { name: "unknown", mask: 0x08 },
+    Dip { name: "hide coin info", mask: 0x80 },
+];
+
+const BALLBOMB_DIPS: [Dip; 3] = [
+    Dip { name: "lives (3, 4, 5, 6)", mask: 0x03 },
+    Dip { name: "bonus life at 1000 instead of 1500", mask: 0x08 },
+    Dip { name: "hide coin info", mask: 0x80 },
+];
+
+pub const INVADERS: Driver = Driver {
+    name: "invaders",
+    description: "Space Invaders (Midway)",
+    roms: &[
+	RomFile { name: "invaders.h", offs: 0x0000, size: 0x800, crc: Some(0x734f5ad8) },
+	RomFile { name: "invaders.g", offs: 0x0800, size: 0x800, crc: Some(0x6bfaca4a) },
+	RomFile { name: "invaders.f", offs: 0x1000, size: 0x800, crc: Some(0x0ccead96) },
+	RomFile { name: "invaders.e", offs: 0x1800, size: 0x800, crc: Some(0x14e538b0) },
+    ],
+    map: &MIDWAY_MAP,
+    addr_mask: 0x3fff,
+    reads: &MIDWAY_READS,
+    writes: &MIDWAY_WRITES,
+    inputs: &INVADERS_INPUTS,
+    dips: &INVADERS_DIPS,
+    dip_default: 0,
+    samples: &INVADERS_SAMPLES,
+    color: None,
+};
+
+pub const INVADPT2: Driver = Driver {
+    name: "invadpt2",
+    description: "Space Invaders Part II (Taito)",
+    roms: &[
+	RomFile { name: "pv01", offs: 0x0000, size: 0x800, crc: None },
+	RomFile { name: "pv02", offs: 0x0800, size: 0x800, crc: None },
+	RomFile { name: "pv03", offs: 0x1000, size: 0x800, crc: None },
+	RomFile { name: "pv04", offs: 0x1800, size: 0x800, crc: None },
+	RomFile { name: "pv05", offs: 0x4000, size: 0x800, crc: None },
+    ],
+    map: &TAITO_MAP,
+    addr_mask: 0x7fff,
+    reads: &MIDWAY_READS,
+    writes: &MIDWAY_WRITES,
+    inputs: &INVADERS_INPUTS,
+    dips: &INVADERS_DIPS,
+    dip_default: 0,
+    samples: &INVADERS_SAMPLES,
+    color: Some(&ColorProm {
+	proms: &[
+	    RomFile { name: "pv06.1", offs: 0x000, size: 0x400, crc: None },
+	    RomFile { name: "pv07.2", offs: 0x400, size: 0x400, crc: None },
+	],
+	bank: (5, 5),
+	red: (3, 2),
+    }),
+};
+
+pub const LRESCUE: Driver = Driver {
+    name: "lrescue",
+    description: "Lunar Rescue (Taito)",
+    roms: &[
+	RomFile { name: "lrescue.1", offs: 0x0000, size: 0x800, crc: None },
+	RomFile { name: "lrescue.2", offs: 0x0800, size: 0x800, crc: None },
+	RomFile { name: "lrescue.3", offs: 0x1000, size: 0x800, crc: None },
+	RomFile { name: "lrescue.4", offs: 0x1800, size: 0x800, crc: None },
+	RomFile { name: "lrescue.5", offs: 0x4000, size: 0x800, crc: None },
+	RomFile { name: "lrescue.6", offs: 0x4800, size: 0x800, crc: None },
+    ],
+    map: &TAITO_MAP,
+    addr_mask: 0x7fff,
+    reads: &MIDWAY_READS,
+    writes: &MIDWAY_WRITES,
+    inputs: &TAITO_INPUTS,
+    dips: &LRESCUE_DIPS,
+    dip_default: 0,
+    samples: &[],
+    color: None,
+};
+
+pub const BALLBOMB: Driver = Driver {
+    name: "ballbomb",
+    description: "Balloon Bomber (Taito)",
+    roms: &[
+	RomFile { name: "tn01", offs: 0x0000, size: 0x800, crc: None },
+	RomFile { name: "tn02", offs: 0x0800, size: 0x800, crc: None },
+	RomFile { name: "tn03", offs: 0x1000, size: 0x800, crc: None },
+	RomFile { name: "tn04", offs: 0x1800, size: 0x800, crc: None },
+	RomFile { name: "tn05-1", offs: 0x4000, size: 0x800, crc: None },
+    ],
+    map: &TAITO_MAP,
+    addr_mask: 0x7fff,
+    reads: &MIDWAY_READS,
+    writes: &MIDWAY_WRITES,
+    inputs: &TAITO_INPUTS,
+    dips: &BALLBOMB_DIPS,
+    dip_default: 0,
+    samples: &[],
+    color: None,
+};
+
+//a bootleg of invaders on the same board, so everything but the roms is
+//shared. they're 1K chips with gaps between them
+pub const SPACEATT: Driver = Driver {
+    name: "spaceatt",
+    description: "Space Attack (bootleg)",
+    roms: &[
+	RomFile { name: "h", offs: 0x0000, size: 0x400, crc: None },
+	RomFile { name: "sv02.bin", offs: 0x0400, size: 0x400, crc: None },
+	RomFile { name: "f", offs: 0x0800, size: 0x400, crc: None },
+	RomFile { name: "c", offs: 0x1400, size: 0x400, crc: None },
+	RomFile { name: "b", offs: 0x1800, size: 0x400, crc: None },
+	RomFile { name: "a", offs: 0x1c00, size: 0x400, crc: None },
+    ],
+    ..INVADERS
+};
+
+//gun aim is a rotary control on the real thing, it's left centred here
+pub const GUNFIGHT: Driver = Driver {
+    name: "gunfight",
+    description: "Gun Fight (Midway)",
+    roms: &[
+	RomFile { name: "7609h.bin", offs: 0x0000, size: 0x400, crc: None },
+	RomFile { name: "7609g.bin", offs: 0x0400, size: 0x400, crc: None },
+	RomFile { name: "7609f.bin", offs: 0x0800, size: 0x400, crc: None },
+	RomFile { name: "7609e.bin", offs: 0x0c00, size: 0x400, crc: None },
+    ],
+    map: &MIDWAY_MAP,
+    addr_mask: 0x3fff,
+    reads: &MIDWAY_READS,
+    writes: &[
+	(1, PortWrite::Sound(0, 8)),
+	(2, PortWrite::ShiftAmount),
+	(4, PortWrite::ShiftData),
+    ],
+    inputs: &[
+	InputPort {
+	    bits: &[(0, In(P1Up)), (1, In(P1Down)), (2, In(P1Left)), (3, In(P1Right)),
+		    (7, In(P1Fire))],
+	    active_low: 0x8f,
+	},
+	InputPort {
+	    bits: &[(0, In(P2Up)), (1, In(P2Down)), (2, In(P2Left)), (3, In(P2Right)),
+		    (7, In(P2Fire))],
+	    active_low: 0x8f,
+	},
+	InputPort {
+	    bits: &[(0, Sw(0)), (1, Sw(1)), (2, Sw(2)), (3, Sw(3)), (4, Sw(4)), (5, Sw(5)),
+		    (6, In(Coin)), (7, In(P1Start))],
+	    active_low: 0xc0,
+	},
+    ],
+    dips: &[
+	Dip { name: "coinage", mask: 0x0f },
+	Dip { name: "game time", mask: 0x30 },
+    ],
+    dip_default: 0,
+    samples: &[],
+    color: None,
+};
+
+pub const SEAWOLF: Driver = Driver {
+    name: "seawolf",
+    description: "Sea Wolf (Midway)",
+    roms: &[
+	RomFile { name: "sw0041.h", offs: 0x0000, size: 0x400, crc: None },
+	RomFile { name: "sw0042.g", offs: 0x0400, size: 0x400, crc: None },
+	RomFile { name: "sw0043.f", offs: 0x0800, size: 0x400, crc: None },
+	RomFile { name: "sw0044.e", offs: 0x0c00, size: 0x400, crc: None },
+    ],
+    map: &MIDWAY_MAP,
+    addr_mask: 0x3fff,
+    reads: &[
+	(0, PortRead::ShifterReversed),
+	(1, PortRead::Inputs(0)),
+	(2, PortRead::Inputs(1)),
+	(3, PortRead::Shifter),
+    ],
+    writes: &[
+	(1, PortWrite::Ignore), //explosion lamp
+	(2, PortWrite::Ignore), //periscope lamp
+	(3, PortWrite::ShiftData),
+	(4, PortWrite::ShiftAmount),
+	(5, PortWrite::Sound(0, 5)),
+	(6, PortWrite::Sound(5, 5)),
+    ],
+    inputs: &[
+	//the periscope is an analog control, left and right nudge it here
+	InputPort { bits: &[(0, In(P1Left)), (1, In(P1Right)), (5, In(P1Fire))], active_low: 0 },
+	InputPort {
+	    bits: &[(0, Sw(0)), (1, Sw(1)), (2, Sw(2)), (3, Sw(3)), (4, Sw(4)), (5, Sw(5)),
+		    (6, In(Coin)), (7, In(P1Start))],
+	    active_low: 0,
+	},
+    ],
+    dips: &[
+	Dip { name: "game time", mask: 0x03 },
+	Dip { name: "coinage", mask: 0x0c },
+	Dip { name: "extended time at", mask: 0x30 },
+    ],
+    dip_default: 0,
+    samples: &[],
+    color: None,
+};
+
+pub const BOOTHILL: Driver = Driver {
+    name: "boothill",
+    description: "Boot Hill (Midway)",
+    roms: &[
+	RomFile { name: "romh.cpu", offs: 0x0000, size: 0x800, crc: None },
+	RomFile { name: "romg.cpu", offs: 0x0800, size: 0x800, crc: None },
+	RomFile { name: "romf.cpu", offs: 0x1000, size: 0x800, crc: None },
+	RomFile { name: "rome.cpu", offs: 0x1800, size: 0x800, crc: None },
+    ],
+    map: &MIDWAY_MAP,
+    addr_mask: 0x3fff,
+    reads: &MIDWAY_READS,
+    writes: &[
+	(1, PortWrite::ShiftAmount),
+	(2, PortWrite::ShiftData),
+	(3, PortWrite::Sound(0, 8)),
+	(4, PortWrite::Watchdog),
+	(5, PortWrite::Sound(8, 8)),
+    ],
+    inputs: &[
+	InputPort {
+	    bits: &[(0, In(P1Up)), (1, In(P1Down)), (2, In(P1Left)), (3, In(P1Right)),
+		    (7, In(P1Fire))],
+	    active_low: 0x8f,
+	},
+	InputPort {
+	    bits: &[(0, In(P2Up)), (1, In(P2Down)), (2, In(P2Left)), (3, In(P2Right)),
+		    (7, In(P2Fire))],
+	    active_low: 0x8f,
+	},
+	InputPort {
+	    bits: &[(0, Sw(0)), (1, Sw(1)), (2, Sw(2)), (3, Sw(3)),
+		    (6, In(Coin)), (7, In(P1Start))],
+	    active_low: 0xc0,
+	},
+    ],
+    dips: &[
+	Dip { name: "coinage", mask: 0x03 },
+	Dip { name: "game time", mask: 0x0c },
+    ],
+    dip_default: 0,
+    samples: &[],
+    color: None,
+};
+
+pub const DRIVERS: [&Driver; 8] = [
+    &INVADERS, &INVADPT2, &LRESCUE, &BALLBOMB, &SPACEATT, &GUNFIGHT, &SEAWOLF, &BOOTHILL,
+];
+
+pub fn find(name: &str) -> Option<&'static Driver> {
+    DRIVERS.iter().copied().find(|d| d.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn in_rom(d: &Driver, start: usize, len: usize) -> bool {
+	d.map.iter().any(|r| r.mem == Mem::Rom && r.start as usize <= start && start + len <= r.end as usize + 1)
+    }
+
+    //every chip lands in rom without overlapping another, and no port
+    //is claimed twice
+    #[test]
+    fn tables_agree() {
+	for (i, d) in DRIVERS.iter().enumerate() {
+	    assert!(DRIVERS[..i].iter().all(|o| o.name != d.name), "{} twice", d.name);
+	    for (j, rom) in d.roms.iter().enumerate() {
+		assert!(in_rom(d, rom.offs, rom.size), "{}: {} outside rom", d.name, rom.name);
+		for other in &d.roms[..j] {
+		    let apart = rom.offs >= other.offs + other.size || other.offs >= rom.offs + rom.size;
+		    assert!(apart, "{}: {} overlaps {}", d.name, rom.name, other.name);
+		}
+	    }
+	    for (j, (port, _)) in d.reads.iter().enumerate() {
+		assert!(d.reads[..j].iter().all(|(p, _)| p != port), "{}: read port {port}", d.name);
+	    }
+	    for (j, (port, _)) in d.writes.iter().enumerate() {
+		assert!(d.writes[..j].iter().all(|(p, _)| p != port), "{}: write port {port}", d.name);
+	    }
+	    for (port, r) in d.reads {
+		if let PortRead::Inputs(n) = r {
+		    assert!(*n < d.inputs.len(), "{}: port {port} has no inputs", d.name);
+		}
+	    }
+	    for (port, w) in d.writes {
+		if let PortWrite::Sound(first, count) = w {
+		    assert!(first + count <= 16, "{}: port {port} sounds", d.name);
+		}
+	    }
+	}
+    }
+}
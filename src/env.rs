@@ -0,0 +1,367 @@
+//a gym style interface for training agents on Space Invaders. actions
+//go in as the player 1 controls, the screen comes out, and the reward
+//and whether the game is over are read from the game's work ram, so
+//this only makes sense with the invaders roms
+use crate::batch::run_frame;
+use crate::cpu::Cpu;
+use crate::driver::Input;
+use crate::state::InvadersState;
+use crate::video::{self, Overlay, HEIGHT, WIDTH};
+
+//long enough for the attract mode to start up and take a coin
+const BOOT_FRAMES: usize = 120;
+const START_TIMEOUT: usize = 600;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Action {
+    pub left: bool,
+    pub right: bool,
+    pub fire: bool,
+}
+
+impl Action {
+    //the combinations that make sense, for agents that pick by index
+    pub const ALL: [Action; 6] = [
+	Action { left: false, right: false, fire: false },
+	Action { left: false, right: false, fire: true },
+	Action { left: false, right: true, fire: false },
+	Action { left: true, right: false, fire: false },
+	Action { left: false, right: true, fire: true },
+	Action { left: true, right: false, fire: true },
+    ];
+}
+
+#[derive(Clone, Debug)]
+pub enum ObsKind {
+    Rgb(Overlay), //the rotated screen as the cabinet shows it
+    Gray(usize), //lit pixels averaged over n by n blocks, 0-255
+}
+
+#[derive(Clone, Debug)]
+pub enum Observation {
+    Rgb(Vec<u32>),
+    Gray { width: usize, height: usize, pixels: Vec<u8> },
+}
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub obs: ObsKind,
+    pub frame_skip: usize, //frames each action is held for, their rewards add up
+    pub sticky: f32, //chance each frame of repeating the last action instead
+    pub max_frames: usize, //an episode ends after this many frames, 0 for never
+}
+
+impl Default for Options {
+    fn default() -> Self {
+	Options {
+	    obs: ObsKind::Gray(2),
+	    frame_skip: 4,
+	    sticky: 0.25,
+	    max_frames: 0,
+	}
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Info {
+    pub score: u32,
+    pub lives: u8,
+    pub frame: usize, //frames into the episode
+}
+
+pub struct Env {
+    pub cpu: Cpu,
+    start: Cpu, //the machine as it was handed over, reset goes back to it
+    opts: Options,
+    colors: Vec<u32>,
+    rng: u64,
+    last: Action,
+    score: u32,
+    frames: usize,
+}
+
+impl Env {
+    //cpu should have the roms loaded and be reset, like Batch wants
+    pub fn new(cpu: Cpu, opts: Options) -> Self {
+	let colors = match &opts.obs {
+	    ObsKind::Rgb(o) => o.color_map(),
+	    ObsKind::Gray(_) => Vec::new(),
+	};
+	Env {
+	    cpu: cpu.clone(),
+	    start: cpu,
+	    opts,
+	    colors,
+	    rng: 1,
+	    last: Action::default(),
+	    score: 0,
+	    frames: 0,
+	}
+    }
+
+    //power up, drop a coin in and start a one player game. the seed picks
+    //how long to wait beforehand, which is where the game gets its variety
+    pub fn reset(&mut self, seed: u64) -> Observation {
+	self.cpu = self.start.clone();
+	self.rng = seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1;
+	self.last = Action::default();
+	self.frames = 0;
+	let wait = BOOT_FRAMES + (self.next() % 60) as usize;
+	self.frames_with(wait, None);
+	self.frames_with(2, Some(Input::Coin));
+	self.frames_with(30, None);
+	self.frames_with(2, Some(Input::P1Start));
+	for _ in 0..START_TIMEOUT {
+	    if self.state().playing {
+		break;
+	    }
+	    run_frame(&mut self.cpu);
+	}
+	self.score = self.info().score;
+	self.observe()
+    }
+
+    pub fn step(&mut self, action: Action) -> (Observation, f32, bool, Info) {
+	let mut done = false;
+	for _ in 0..self.opts.frame_skip.max(1) {
+	    let a = if self.chance() < self.opts.sticky { self.last } else { action };
+	    self.last = a;
+	    self.cpu.bus.set_input(Input::P1Left, a.left);
+	    self.cpu.bus.set_input(Input::P1Right, a.right);
+	    self.cpu.bus.set_input(Input::P1Fire, a.fire);
+	    run_frame(&mut self.cpu);
+	    self.frames += 1;
+	    done = !self.state().playing
+		|| (self.opts.max_frames != 0 && self.frames >= self.opts.max_frames);
+	    if done {
+		break;
+	    }
+	}
+	let info = self.info();
+	let reward = info.score as f32 - self.score as f32;
+	self.score = info.score;
+	(self.observe(), reward, done, info)
+    }
+
+    pub fn state(&self) -> InvadersState {
+	InvadersState::read(&self.cpu.bus)
+    }
+
+    pub fn info(&self) -> Info {
+	let s = self.state();
+	Info {
+	    score: s.scores[0],
+	    lives: s.lives[0] + s.playing as u8,
+	    frame: self.frames,
+	}
+    }
+
+    pub fn observe(&self) -> Observation {
+	let display = &self.cpu.bus.display;
+	match self.opts.obs {
+	    ObsKind::Rgb(_) => {
+		let mut frame = vec![0; WIDTH * HEIGHT];
+		video::render(display, &self.colors, &mut frame);
+		Observation::Rgb(frame)
+	    },
+	    ObsKind::Gray(n) => {
+		let n = n.max(1);
+		let (width, height) = (WIDTH / n, HEIGHT / n);
+		let mut pixels = vec![0; width * height];
+		for (i, p) in pixels.iter_mut().enumerate() {
+		    let (bx, by) = (i % width * n, i / width * n);
+		    let mut lit = 0;
+		    for y in by..by + n {
+			for x in bx..bx + n {
+			    //the monitor is on its side, screen columns are vram rows
+			    let col = HEIGHT - 1 - y;
+			    lit += (display[x * 32 + col / 8] >> (col % 8)) as usize & 1;
+			}
+		    }
+		    *p = (lit * 255 / (n * n)) as u8;
+		}
+		Observation::Gray { width, height, pixels }
+	    },
+	}
+    }
+
+    fn frames_with(&mut self, frames: usize, input: Option<Input>) {
+	if let Some(i) = input {
+	    self.cpu.bus.set_input(i, true);
+	}
+	for _ in 0..frames {
+	    run_frame(&mut self.cpu);
+	}
+	if let Some(i) = input {
+	    self.cpu.bus.set_input(i, false);
+	}
+    }
+
+    //xorshift, all the randomness needs
+    fn next(&mut self) -> u64 {
+	self.rng ^= self.rng << 13;
+	self.rng ^= self.rng >> 7;
+	self.rng ^= self.rng << 17;
+	self.rng
+    }
+
+    fn chance(&mut self) -> f32 {
+	(self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+
+    //a stand in for the game that keeps its variables where Space
+    //Invaders does. the vblank interrupt counts frames at 2300, starts a
+    //game with two ships in reserve on P1 start, scores 10 for each frame
+    //fire is held and takes a ship for each frame left is held
+    const GAME: [u8; 0x6a] = [
+	0x31, 0x00, 0x24, //lxi sp,2400
+	0xfb, //ei
+	0xc3, 0x04, 0x00, //idle: jmp idle
+	0x00,
+	0xfb, 0xc9, //ei  ret
+	0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+	0xf5, //push psw
+	0x21, 0x00, 0x23, //lxi h,2300
+	0x34, //inr m
+	0xdb, 0x01, //in 1
+	0x47, //mov b,a
+	0x3a, 0xef, 0x20, //lda 20ef
+	0xb7, //ora a
+	0xc2, 0x37, 0x00, //jnz play
+	0x78, //mov a,b
+	0xe6, 0x04, //ani 04
+	0xca, 0x67, 0x00, //jz done
+	0x3e, 0x01, //mvi a,1
+	0x32, 0xef, 0x20, //sta 20ef
+	0x3e, 0x02, //mvi a,2
+	0x32, 0xff, 0x21, //sta 21ff
+	0x3e, 0xff, //mvi a,ff
+	0x32, 0x00, 0x24, //sta 2400, the bottom left corner
+	0xc3, 0x67, 0x00, //jmp done
+	0x78, //play: mov a,b
+	0xe6, 0x10, //ani 10
+	0xca, 0x4f, 0x00, //jz left
+	0x3a, 0xf8, 0x20, //lda 20f8
+	0xc6, 0x10, //adi 10
+	0x27, //daa
+	0x32, 0xf8, 0x20, //sta 20f8
+	0x3a, 0xf9, 0x20, //lda 20f9
+	0xce, 0x00, //aci 0
+	0x27, //daa
+	0x32, 0xf9, 0x20, //sta 20f9
+	0x78, //left: mov a,b
+	0xe6, 0x20, //ani 20
+	0xca, 0x67, 0x00, //jz done
+	0x3a, 0xff, 0x21, //lda 21ff
+	0xd6, 0x01, //sui 1
+	0xda, 0x63, 0x00, //jc over
+	0x32, 0xff, 0x21, //sta 21ff
+	0xc3, 0x67, 0x00, //jmp done
+	0xaf, //over: xra a
+	0x32, 0xef, 0x20, //sta 20ef
+	0xf1, //done: pop psw
+	0xfb, 0xc9, //ei  ret
+    ];
+
+    const FIRE: Action = Action { left: false, right: false, fire: true };
+    const LEFT: Action = Action { left: true, right: false, fire: false };
+
+    fn env(opts: Options) -> Env {
+	let mut cpu = Cpu::new();
+	cpu.bus.load_bin(0, &GAME);
+	cpu.reset();
+	Env::new(cpu, opts)
+    }
+
+    fn machine_frames(env: &Env) -> u8 {
+	env.cpu.bus.peek(0x2300)
+    }
+
+    #[test]
+    fn reset() {
+	let mut e = env(Options::default());
+	let obs = e.reset(3);
+	let info = e.info();
+	assert!(e.state().playing);
+	assert_eq!((info.score, info.lives, info.frame), (0, 3, 0));
+	let Observation::Gray { width, height, pixels } = &obs else {
+	    panic!("expected a gray observation");
+	};
+	assert_eq!((*width, *height, pixels.len()), (112, 128, 112 * 128));
+	//the corner byte is 8 pixels up the left edge, half of each 2x2 block
+	for (i, &p) in pixels.iter().enumerate() {
+	    let want = if i % 112 == 0 && i / 112 >= 124 { 127 } else { 0 };
+	    assert_eq!(p, want, "pixel {},{}", i % 112, i / 112);
+	}
+	//the same seed plays out the same
+	let again = e.reset(3);
+	assert_eq!(format!("{again:?}"), format!("{obs:?}"));
+	let mut fresh = env(Options::default());
+	fresh.reset(3);
+	assert_eq!(e.cpu.bus.frame, fresh.cpu.bus.frame);
+    }
+
+    #[test]
+    fn reward_and_frame_skip() {
+	let mut e = env(Options { sticky: 0.0, ..Options::default() });
+	e.reset(1);
+	let start = machine_frames(&e);
+	let (_, reward, done, info) = e.step(FIRE);
+	assert_eq!((reward, done, info.score, info.frame), (40.0, false, 40, 4));
+	assert_eq!(machine_frames(&e), start.wrapping_add(4));
+	let (_, reward, _, info) = e.step(Action::default());
+	assert_eq!((reward, info.score, info.frame), (0.0, 40, 8));
+	//past 99 the carry goes into the high byte
+	for _ in 0..3 {
+	    e.step(FIRE);
+	}
+	assert_eq!(e.info().score, 160);
+    }
+
+    //with sticky at 1 the first action, doing nothing, is held forever
+    #[test]
+    fn sticky() {
+	let mut e = env(Options { sticky: 1.0, frame_skip: 2, ..Options::default() });
+	e.reset(1);
+	let start = machine_frames(&e);
+	for _ in 0..5 {
+	    let (_, reward, _, _) = e.step(FIRE);
+	    assert_eq!(reward, 0.0);
+	}
+	assert_eq!(machine_frames(&e), start.wrapping_add(10));
+    }
+
+    //done comes with the last ship, part way through a skip
+    #[test]
+    fn last_life() {
+	let mut e = env(Options { sticky: 0.0, frame_skip: 1, ..Options::default() });
+	e.reset(1);
+	let lives: Vec<(u8, bool)> = (0..3).map(|_| {
+	    let (_, _, done, info) = e.step(LEFT);
+	    (info.lives, done)
+	}).collect();
+	assert_eq!(lives, [(2, false), (1, false), (0, true)]);
+
+	let mut e = env(Options { sticky: 0.0, frame_skip: 8, ..Options::default() });
+	e.reset(1);
+	let (_, _, done, info) = e.step(LEFT);
+	assert_eq!((done, info.frame), (true, 3));
+    }
+
+    #[test]
+    fn max_frames() {
+	let mut e = env(Options { sticky: 0.0, frame_skip: 4, max_frames: 10, ..Options::default() });
+	e.reset(1);
+	let done: Vec<(bool, usize)> = (0..3).map(|_| {
+	    let (_, _, done, info) = e.step(Action::default());
+	    (done, info.frame)
+	}).collect();
+	assert_eq!(done, [(false, 4), (false, 8), (true, 10)]);
+    }
+}
@@ -0,0 +1,139 @@
+use crate::video::{WIDTH, HEIGHT, scale_rgb, add_rgb, max_rgb};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Upscale {
+    None,
+    Scale2x,
+}
+
+impl Upscale {
+    pub fn from_name(name: &str) -> Option<Self> {
+	match name {
+	    "none" => Some(Upscale::None),
+	    "scale2x" => Some(Upscale::Scale2x),
+	    _ => None,
+	}
+    }
+
+    pub fn factor(&self) -> usize {
+	match self {
+	    Upscale::None => 1,
+	    Upscale::Scale2x => 2,
+	}
+    }
+}
+
+//software crt effects, everything here runs on the cpu on 0x00RRGGBB frames.
+//phosphor and bloom model the tube so they run on the bare playfield,
+//upscaling and scanlines run last on the finished picture
+pub struct Chain {
+    phosphor: f32,
+    bloom: f32,
+    scanlines: f32,
+    upscale: Upscale,
+    prev: Vec<u32>,
+    tmp: Vec<u32>,
+    out: Vec<u32>,
+}
+
+impl Chain {
+    pub fn new(phosphor: f32, bloom: f32, scanlines: f32, upscale: Upscale) -> Self {
+	let f = upscale.factor();
+	Chain {
+	    phosphor: phosphor.clamp(0.0, 1.0),
+	    bloom: bloom.max(0.0),
+	    scanlines: scanlines.clamp(0.0, 1.0),
+	    upscale,
+	    prev: vec![0; WIDTH * HEIGHT],
+	    tmp: vec![0; WIDTH * HEIGHT],
+	    out: vec![0; WIDTH * HEIGHT * f * f],
+	}
+    }
+
+    pub fn width(&self) -> usize {
+	WIDTH * self.upscale.factor()
+    }
+
+    pub fn height(&self) -> usize {
+	HEIGHT * self.upscale.factor()
+    }
+
+    pub fn monitor(&mut self, frame: &mut [u32]) {
+	if self.phosphor > 0.0 {
+	    //lit phosphor fades out over a few frames instead of going dark
+	    //at once, which also steadies the flickering shots
+	    for (f, p) in frame.iter_mut().zip(self.prev.iter_mut()) {
+		*f = max_rgb(*f, scale_rgb(*p, self.phosphor));
+		*p = *f;
+	    }
+	}
+
+	if self.bloom > 0.0 {
+	    self.tmp.copy_from_slice(frame);
+	    for y in 0..HEIGHT {
+		for x in 0..WIDTH {
+		    let mut sum = [0u32; 3];
+		    for ny in y.saturating_sub(1)..(y + 2).min(HEIGHT) {
+			for nx in x.saturating_sub(1)..(x + 2).min(WIDTH) {
+			    let c = self.tmp[ny * WIDTH + nx];
+			    sum[0] += (c >> 16) & 0xff;
+			    sum[1] += (c >> 8) & 0xff;
+			    sum[2] += c & 0xff;
+			}
+		    }
+		    let glow = ((sum[0] / 9) << 16) | ((sum[1] / 9) << 8) | (sum[2] / 9);
+		    let i = y * WIDTH + x;
+		    frame[i] = add_rgb(frame[i], scale_rgb(glow, self.bloom));
+		}
+	    }
+	}
+    }
+
+    pub fn present<'a>(&'a mut self, frame: &'a [u32]) -> &'a [u32] {
+	if self.upscale == Upscale::None && self.scanlines == 0.0 {
+	    return frame;
+	}
+	match self.upscale {
+	    Upscale::None => self.out.copy_from_slice(frame),
+	    Upscale::Scale2x => scale2x(frame, &mut self.out),
+	}
+
+	if self.scanlines > 0.0 {
+	    //the monitor is on its side, so the gaps between lines run
+	    //top to bottom in the rotated picture
+	    let w = self.width();
+	    let k = 1.0 - self.scanlines;
+	    for (i, c) in self.out.iter_mut().enumerate() {
+		if (i % w) & 1 != 0 {
+		    *c = scale_rgb(*c, k);
+		}
+	    }
+	}
+	&self.out
+    }
+}
+
+fn scale2x(src: &[u32], dst: &mut [u32]) {
+    let w2 = WIDTH * 2;
+    for y in 0..HEIGHT {
+	for x in 0..WIDTH {
+	    let e = src[y * WIDTH + x];
+	    let b = if y > 0 { src[(y - 1) * WIDTH + x] } else { e };
+	    let h = if y < HEIGHT - 1 { src[(y + 1) * WIDTH + x] } else { e };
+	    let d = if x > 0 { src[y * WIDTH + x - 1] } else { e };
+	    let f = if x < WIDTH - 1 { src[y * WIDTH + x + 1] } else { e };
+	    let o = y * 2 * w2 + x * 2;
+	    if b != h && d != f {
+		dst[o] = if d == b { d } else { e };
+		dst[o + 1] = if b == f { f } else { e };
+		dst[o + w2] = if d == h { d } else { e };
+		dst[o + w2 + 1] = if h == f { f } else { e };
+	    } else {
+		dst[o] = e;
+		dst[o + 1] = e;
+		dst[o + w2] = e;
+		dst[o + w2 + 1] = e;
+	    }
+	}
+    }
+}
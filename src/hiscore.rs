@@ -0,0 +1,117 @@
+//keeps Space Invaders' high score between runs. the game only has it in
+//ram, so the frontend saves the two bytes to a file when it exits and
+//puts them back once the game has set up its variables at power on
+use crate::bus::{Bus, InvBus};
+use crate::state::{bcd, HIGH_SCORE};
+use std::fs;
+use std::io::ErrorKind;
+
+//where on screen each score is drawn. the game copies these in with the
+//rest of its variables when it starts, after which it won't clear the
+//high score again, so seeing all three means it's safe to restore
+const SCORE_LOCS: [(u16, u16); 3] = [(0x20f6, 0x2f1c), (0x20fa, 0x271c), (0x20fe, 0x391c)];
+
+pub fn path(game: &str) -> String {
+    format!("{game}.hi")
+}
+
+//the saved score, None if there isn't one yet
+pub fn load(path: &str) -> Result<Option<[u8; 2]>, String> {
+    let buf = match fs::read(path) {
+	Ok(b) => b,
+	Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+	Err(e) => return Err(format!("{path}: {e}")),
+    };
+    match buf[..] {
+	[lo, hi] if is_bcd(lo) && is_bcd(hi) => Ok(Some([lo, hi])),
+	_ => Err(format!("{path}: not a high score, expected 2 bytes of BCD")),
+    }
+}
+
+pub fn save(path: &str, score: [u8; 2]) -> Result<(), String> {
+    fs::write(path, score).map_err(|e| format!("{path}: {e}"))
+}
+
+pub fn ready(bus: &InvBus) -> bool {
+    SCORE_LOCS.iter().all(|&(addr, loc)| {
+	(bus.peek(addr) as u16 | (bus.peek(addr + 1) as u16) << 8) == loc
+    })
+}
+
+//the high score in ram, once the game has set it up and if it makes sense
+pub fn current(bus: &InvBus) -> Option<[u8; 2]> {
+    let score = [bus.peek(HIGH_SCORE), bus.peek(HIGH_SCORE + 1)];
+    (ready(bus) && is_bcd(score[0]) && is_bcd(score[1])).then_some(score)
+}
+
+pub fn restore(bus: &mut InvBus, score: [u8; 2]) {
+    bus.write_byte(HIGH_SCORE, score[0]);
+    bus.write_byte(HIGH_SCORE + 1, score[1]);
+}
+
+//for comparing scores
+pub fn value(score: [u8; 2]) -> u32 {
+    bcd(score[0]) + bcd(score[1]) * 100
+}
+
+fn is_bcd(b: u8) -> bool {
+    b >> 4 < 10 && b & 0xf < 10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp(name: &str) -> String {
+	let path = std::env::temp_dir().join(format!("invaders-hi-test-{}-{name}", std::process::id()));
+	path.to_str().unwrap().to_string()
+    }
+
+    //what the game copies in at power on, with a score already there
+    fn set_up(bus: &mut InvBus) {
+	for (addr, loc) in SCORE_LOCS {
+	    bus.write_byte(addr, loc as u8);
+	    bus.write_byte(addr + 1, (loc >> 8) as u8);
+	}
+    }
+
+    #[test]
+    fn bad_files() {
+	let path = temp("bad");
+	assert_eq!(load(&path), Ok(None));
+	for buf in [&[0x50][..], &[0x50, 0x12, 0x00], &[0x5a, 0x12], &[0x50, 0xa2]] {
+	    fs::write(&path, buf).unwrap();
+	    assert!(load(&path).is_err(), "{buf:02x?}");
+	}
+	fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ram_set_up() {
+	let mut bus = InvBus::new();
+	restore(&mut bus, [0x50, 0x12]);
+	assert!(!ready(&bus));
+	assert_eq!(current(&bus), None);
+	set_up(&mut bus);
+	assert!(ready(&bus));
+	assert_eq!(current(&bus), Some([0x50, 0x12]));
+	//half drawn or trampled isn't a score
+	bus.write_byte(HIGH_SCORE, 0x5f);
+	assert_eq!(current(&bus), None);
+    }
+
+    #[test]
+    fn round_trip() {
+	let path = temp("trip");
+	let mut bus = InvBus::new();
+	set_up(&mut bus);
+	save(&path, [0x90, 0x07]).unwrap();
+	let score = load(&path).unwrap().unwrap();
+	restore(&mut bus, score);
+	assert_eq!(current(&bus), Some([0x90, 0x07]));
+	assert_eq!(value(score), 790);
+	assert!(value([0x00, 0x10]) > value([0x99, 0x09]));
+	fs::remove_file(&path).unwrap();
+    }
+}
+
@@ -0,0 +1,16 @@
+pub mod artwork;
+pub mod batch;
+pub mod bus;
+pub mod config;
+pub mod cpu;
+pub mod driver;
+pub mod env;
+pub mod filter;
+pub mod hiscore;
+pub mod mb14241;
+pub mod rom;
+pub mod state;
+pub mod video;
+
+#[cfg(test)]
+mod testutil;
@@ -1,52 +1,156 @@
-mod cpu;
-mod bus;
-
-use crate::bus::Bus;
-use std::env;
+use invaders::{artwork, bus, config, cpu, driver, filter, hiscore, rom, state, video};
+use invaders::bus::Bus;
+use invaders::driver::Input;
 use std::thread;
 use std::time;
 use std::io;
 use std::io::prelude::*;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
+use sdl2::rect::Rect;
+use sdl2::video::FullscreenType;
 use sdl2::mixer::{InitFlag, AUDIO_S16LSB, DEFAULT_CHANNELS};
 
-fn draw(bus: &mut bus::InvBus, tex: &mut sdl2::render::Texture) {
+fn best_score(a: Option<[u8; 2]>, b: Option<[u8; 2]>) -> Option<[u8; 2]> {
+    match (a, b) {
+	(Some(a), Some(b)) => Some(if hiscore::value(b) > hiscore::value(a) { b } else { a }),
+	_ => a.or(b),
+    }
+}
+
+fn draw(frame: &[u32], w: usize, h: usize, tex: &mut sdl2::render::Texture) {
     tex.with_lock(None, |buf: &mut [u8], pitch: usize| {
-	for x in (0..256).step_by(8) {
-	    for y in 0..224 {
-		for b in (0..8).rev() {
-		    let bufx: usize = y;
-		    let bufy: usize = (-(x as i16 + b as i16) + 256 - 1) as usize;
-		    let buf_offs: usize = bufy * pitch + bufx;
-		    let offs: usize = (y * (256 / 8)) + (x / 8);
-		    let px: u8 = (bus.vram[offs] >> b) & 1;
-		    let c: u8 = match px {
-			0 => 0x00,
-			_ => 0xff,
-		    };
-		    buf[buf_offs] = c;
-		}
+	for y in 0..h {
+	    for x in 0..w {
+		let offs = y * pitch + x * 4;
+		let c = 0xff000000 | frame[y * w + x];
+		buf[offs..offs + 4].copy_from_slice(&c.to_ne_bytes());
 	    }
 	}
     }).unwrap();
 }
-	    
+
+//the latest picture from the machine with all the effects applied
+fn compose<'a>(cpu: &mut cpu::Cpu, screen: &mut video::Screen, frame: &'a mut [u32],
+	       filters: &'a mut filter::Chain, backdrop: Option<&artwork::Backdrop>) -> &'a [u32] {
+    if let Some(cells) = cpu.bus.take_cell_colors() {
+	screen.set_colors(video::cell_color_map(&cells));
+    }
+    let dirty = cpu.bus.take_display_dirty();
+    screen.update(&cpu.bus.display, &dirty);
+    frame.copy_from_slice(&screen.px);
+    filters.monitor(frame);
+    if let Some(b) = backdrop {
+	b.composite(frame);
+    }
+    filters.present(frame)
+}
+
+fn key_input(kc: Keycode) -> Option<Input> {
+    match kc {
+	Keycode::LEFT => Some(Input::P1Left),
+	Keycode::RIGHT => Some(Input::P1Right),
+	Keycode::UP => Some(Input::P1Up),
+	Keycode::DOWN => Some(Input::P1Down),
+	Keycode::LCTRL => Some(Input::P1Fire),
+	Keycode::C => Some(Input::Coin),
+	Keycode::RETURN => Some(Input::P1Start),
+	Keycode::NUM_2 => Some(Input::P2Start),
+	_ => None,
+    }
+}
+
+fn print_log(cpu: &mut cpu::Cpu) {
+    for msg in cpu.bus.take_log() {
+	println!("{msg}");
+    }
+}
+
+fn list_games() {
+    for d in driver::DRIVERS {
+	println!("{:10} {}", d.name, d.description);
+	for dip in d.dips {
+	    println!("{:10}   dip {:02x}: {}", "", dip.mask, dip.name);
+	}
+    }
+}
+
 fn main() {
     let mut cpu = cpu::Cpu::new();
     let mut stdin = io::stdin();
 
-    let path = env::args().nth(1).expect("Usage: invaders <path>");
-    let buf: Vec<u8> = std::fs::read(path).unwrap();
+    let cfg = config::Config::from_args();
+    if cfg.game.as_deref() == Some("list") {
+	list_games();
+	return;
+    }
+    let game = match &cfg.game {
+	Some(name) => driver::find(name)
+	    .unwrap_or_else(|| panic!("unknown game {name}, --game list shows them all")),
+	None => cfg.rom_path.as_deref().and_then(rom::detect).unwrap_or(&driver::INVADERS),
+    };
+    let rom_path = cfg.rom_path.clone().unwrap_or_else(|| rom::default_path(game.name));
+    cpu.bus = bus::InvBus::with_driver(game);
+    if let Some(dip) = cfg.dip {
+	cpu.bus.dip = dip;
+    }
+    cpu.bus.log_unmapped = cfg.log_unmapped;
+    cpu.bus.verbose = true;
+    if let Some(v) = cfg.open_bus {
+	cpu.bus.open_bus = v;
+    }
+    cpu.bus.watchdog_frames = cfg.watchdog;
+    let buf = rom::load(&rom_path, game.roms, cfg.check_crc).unwrap_or_else(|e| {
+	println!("{e}");
+	std::process::exit(1);
+    });
     //let stub_buf: Vec<u8> = std::fs::read("cpmstub.bin").unwrap();
     //cpu.bus.load_bin(0xdc00, &stub_buf);
     //cpu.bus.write_byte(5, 0xc3);
     //cpu.bus.write_word(6, 0x00dc); //jmp $dc00
     cpu.bus.load_bin(0, &buf);
+    if let Some(c) = game.color {
+	match rom::load_set(&rom_path, c.proms, cfg.check_crc) {
+	    Ok(p) => cpu.bus.load_proms(&p),
+	    Err(e) => println!("no colour proms, running in black and white: {e}"),
+	}
+    }
     cpu.reset();
     //cpu.pc = 0x100;
 
+    let mut screen = video::Screen::new(cfg.overlay.color_map());
+    let mut frame = vec![0u32; video::WIDTH * video::HEIGHT];
+    let mut filters = filter::Chain::new(cfg.phosphor, cfg.bloom, cfg.scanlines, cfg.upscale);
+    let (out_w, out_h) = (filters.width(), filters.height());
+    let backdrop = cfg.backdrop.as_ref().and_then(|path| {
+	artwork::Backdrop::load(path, cfg.backdrop_align, cfg.backdrop_offset, cfg.backdrop_brightness)
+	    .map_err(|e| println!("not using backdrop: {e}"))
+	    .ok()
+    });
+
+    if let Some(path) = &cfg.screenshot {
+	//headless, run for a while and save what's on screen
+	let mut frames = 0;
+	while frames < cfg.frames {
+	    if cpu.stuck() {
+		println!("cpu halted with interrupts off on frame {}", cpu.bus.frame);
+		break;
+	    }
+	    let cyc = cpu.step();
+	    cpu.bus.step(cyc);
+	    if cpu.bus.vblank {
+		cpu.bus.vblank = false;
+		frames += 1;
+		print_log(&mut cpu);
+	    }
+	}
+	print_log(&mut cpu);
+	let out = compose(&mut cpu, &mut screen, &mut frame, &mut filters, backdrop.as_ref());
+	artwork::save_png(path, out, out_w, out_h).unwrap();
+	return;
+    }
+
     let context = sdl2::init().unwrap();
     
     let _audio = context.audio().unwrap();
@@ -54,95 +158,163 @@ fn main() {
     let _mixer_context = sdl2::mixer::init(InitFlag::MP3 | InitFlag::FLAC | InitFlag::MOD | InitFlag::OGG)
 	.unwrap();
     sdl2::mixer::allocate_channels(4);
-    let sfx_chunks: [sdl2::mixer::Chunk; 9] = [ sdl2::mixer::Chunk::from_file("sfx/0.wav").unwrap(),
-						sdl2::mixer::Chunk::from_file("sfx/1.wav").unwrap(),
-						sdl2::mixer::Chunk::from_file("sfx/3.wav").unwrap(),
-						sdl2::mixer::Chunk::from_file("sfx/4.wav").unwrap(),
-						sdl2::mixer::Chunk::from_file("sfx/5.wav").unwrap(),
-						sdl2::mixer::Chunk::from_file("sfx/6.wav").unwrap(),
-						sdl2::mixer::Chunk::from_file("sfx/7.wav").unwrap(),
-						sdl2::mixer::Chunk::from_file("sfx/8.wav").unwrap(),
-						sdl2::mixer::Chunk::from_file("sfx/9.wav").unwrap(),
-    ];
+    let sfx_chunks: Vec<sdl2::mixer::Chunk> = game.samples.iter()
+	.map(|f| sdl2::mixer::Chunk::from_file(f).unwrap())
+	.collect();
     
     let mut event_pump = context.event_pump().unwrap();
     let video = context.video().unwrap();
-    let width = 224;
-    let height = 256;
-    let win = video.window("Space Invaders", width, height)
+    let height = video::HEIGHT as u32 * cfg.scale;
+    let width = (height as f32 * cfg.aspect).round() as u32;
+    let mut win = video.window("Space Invaders", width, height)
 	.position_centered()
+	.resizable()
 	.opengl()
 	.build()
 	.unwrap();
+    if cfg.fullscreen {
+	win.set_fullscreen(FullscreenType::Desktop).unwrap();
+    }
     let mut canv = win.into_canvas().build().unwrap();
     let tex_create = canv.texture_creator();
     let mut tex = tex_create
-	.create_texture_streaming(PixelFormatEnum::RGB332, 224, 256)
+	.create_texture_streaming(PixelFormatEnum::ARGB8888, out_w as u32, out_h as u32)
 	.unwrap();
     canv.clear();
     canv.present();
 
-    let cycle_time = time::Duration::from_nanos(50); //2mhz clock
+    //pace by frames, the cpu runs a frame's worth of cycles as fast as it
+    //can and then waits for the next 60th of a second
+    let frame_time = time::Duration::from_nanos(1_000_000_000 / 60);
+    let mut next_frame = time::Instant::now() + frame_time;
+    let mut stuck = false;
+    let mut panel = false;
+    //what's on disk, the best score seen since, and whether it still needs
+    //putting back in ram
+    let hi_path = hiscore::path(game.name);
+    let keep_hi = cfg.hiscore && game.name == "invaders";
+    let saved = if keep_hi {
+	hiscore::load(&hi_path).unwrap_or_else(|e| {
+	    println!("ignoring saved high score: {e}");
+	    None
+	})
+    } else {
+	None
+    };
+    let mut best = saved;
+    let mut restore = saved;
     'running: loop {
-	let now = time::Instant::now();
-	for e in event_pump.poll_iter() {
-	    match e {
-		Event::Quit {..} |
-		Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
-		    break 'running;
-		},
-		Event::KeyDown { keycode: Some(kc), .. } => {
-		    match kc {
-			Keycode::LEFT => cpu.bus.p1_left = true,
-			Keycode::RIGHT => cpu.bus.p1_right = true,
-			Keycode::LCTRL => cpu.bus.p1_fire = true,
-			Keycode::C => cpu.bus.credit = true,
-			Keycode::RETURN => cpu.bus.p1_start = true,
-			_ => {},
-		    };
-		},
-		Event::KeyUp { keycode: Some(kc), .. } => {
-		    match kc {
-			Keycode::LEFT => cpu.bus.p1_left = false,
-			Keycode::RIGHT => cpu.bus.p1_right = false,
-			Keycode::LCTRL => cpu.bus.p1_fire = false,
-			Keycode::C => cpu.bus.credit = false,
-			Keycode::RETURN => cpu.bus.p1_start = false,
-			_ => {},
-		    };
-		},
-		_ => {},
-	    }
-	}
-	
 	let cyc = cpu.step();
-	if cyc == 0 {
-	    break 'running;
-	}
 	cpu.bus.step(cyc);
 	if cpu.bus.vblank {
-	    draw(&mut cpu.bus, &mut tex);
+	    print_log(&mut cpu);
+	    //before any reset below (or the watchdog's) clears it in ram
+	    if keep_hi {
+		best = best_score(best, hiscore::current(&cpu.bus));
+	    }
+	    for e in event_pump.poll_iter() {
+		match e {
+		    Event::Quit {..} |
+		    Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+			break 'running;
+		    },
+		    Event::KeyDown { keycode: Some(Keycode::F11), repeat: false, .. } => {
+			let win = canv.window_mut();
+			let fs = match win.fullscreen_state() {
+			    FullscreenType::Off => FullscreenType::Desktop,
+			    _ => FullscreenType::Off,
+			};
+			win.set_fullscreen(fs).unwrap();
+		    },
+		    //F2 prints what the game is up to every half second
+		    Event::KeyDown { keycode: Some(Keycode::F2), repeat: false, .. } => {
+			if game.name == "invaders" {
+			    panel = !panel;
+			} else {
+			    println!("the state panel only knows Space Invaders");
+			}
+		    },
+		    //F3 presses the reset button, shift+F3 turns it off and on again
+		    Event::KeyDown { keycode: Some(Keycode::F3), keymod, repeat: false, .. } => {
+			if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+			    println!("power cycled on frame {}", cpu.bus.frame);
+			    cpu.power_on();
+			    restore = best;
+			} else {
+			    println!("reset on frame {}", cpu.bus.frame);
+			    cpu.reset();
+			}
+		    },
+		    Event::KeyDown { keycode: Some(kc), .. } => {
+			if let Some(i) = key_input(kc) {
+			    cpu.bus.set_input(i, true);
+			}
+		    },
+		    Event::KeyUp { keycode: Some(kc), .. } => {
+			if let Some(i) = key_input(kc) {
+			    cpu.bus.set_input(i, false);
+			}
+		    },
+		    _ => {},
+		}
+	    }
+
+	    if let Some(score) = restore {
+		if hiscore::ready(&cpu.bus) {
+		    hiscore::restore(&mut cpu.bus, score);
+		    restore = None;
+		}
+	    }
+
+	    if panel && cpu.bus.frame.is_multiple_of(30) {
+		println!("{}", state::InvadersState::read(&cpu.bus));
+	    }
+
+	    //leave the last picture up and say so in the title until it's reset
+	    if cpu.stuck() != stuck {
+		stuck = cpu.stuck();
+		let title = if stuck {
+		    println!("cpu halted with interrupts off on frame {}", cpu.bus.frame);
+		    "Space Invaders (halted, F3 to reset)"
+		} else {
+		    "Space Invaders"
+		};
+		canv.window_mut().set_title(title).unwrap();
+	    }
+
+	    let out = compose(&mut cpu, &mut screen, &mut frame, &mut filters, backdrop.as_ref());
+	    draw(out, out_w, out_h, &mut tex);
 	    cpu.bus.vblank = false;
-	    canv.copy(&tex, None, None).unwrap();
+	    let (out_w, out_h) = canv.output_size().unwrap();
+	    let (x, y, w, h) = video::fit(out_w, out_h, cfg.aspect, cfg.integer_scale);
+	    canv.clear();
+	    canv.copy(&tex, None, Rect::new(x, y, w, h)).unwrap();
 	    canv.present();
 
-	    for i in 0..9 {
+	    for i in 0..sfx_chunks.len() {
 		if cpu.bus.sfx[i] {
 		    sdl2::mixer::Channel::all().halt();
 		    sdl2::mixer::Channel::all().play(&sfx_chunks[i], 0).unwrap();
 		    cpu.bus.sfx[i] = false;
 		}
 	    }
+
+	    let now = time::Instant::now();
+	    if now < next_frame {
+		thread::sleep(next_frame - now);
+	    }
+	    //don't try to catch up if we fell far behind
+	    next_frame = (next_frame + frame_time).max(now);
 	}
-	
-	//let _ = stdin.read(&mut [0u8]).unwrap();
-	let elapsed = now.elapsed();
-	let target = cycle_time.saturating_mul(cyc as u32);
-	//println!("took {} target is {}",
-	//	 elapsed.as_nanos(),
-	//	 target.as_nanos());
-	if elapsed < target {
-	    thread::sleep(target.saturating_sub(elapsed));
+    }
+
+    //only write it when it's gone up
+    if keep_hi {
+	best = best_score(best, hiscore::current(&cpu.bus));
+	if let Some(score) = best.filter(|_| best != saved) {
+	    if let Err(e) = hiscore::save(&hi_path, score) {
+		println!("couldn't save the high score: {e}");
+	    }
 	}
     }
 }
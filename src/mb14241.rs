@@ -0,0 +1,78 @@
+//the fujitsu mb14241 barrel shifter the midway boards use to draw
+//sprites at any x position. the last two bytes written sit side by
+//side in a 16 bit register and the result is the 8 bits starting
+//amount bits in from the left
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Mb14241 {
+    data: u16,
+    amount: u8,
+}
+
+impl Mb14241 {
+    pub fn new() -> Self {
+	Mb14241 { data: 0, amount: 0 }
+    }
+
+    pub fn write_amount(&mut self, data: u8) {
+	self.amount = data & 7;
+    }
+
+    //the new byte goes in the top half, pushing the old one down
+    pub fn write_data(&mut self, data: u8) {
+	self.data = (data as u16) << 8 | self.data >> 8;
+    }
+
+    pub fn read_result(&self) -> u8 {
+	((self.data << self.amount) >> 8) as u8
+    }
+
+    //some boards wire the output pins backwards
+    pub fn read_reversed(&self) -> u8 {
+	self.read_result().reverse_bits()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //c3 written after a5, so the register holds 1100 0011 1010 0101
+    const RESULTS: [u8; 8] = [0xc3, 0x87, 0x0e, 0x1d, 0x3a, 0x74, 0xe9, 0xd2];
+    const REVERSED: [u8; 8] = [0xc3, 0xe1, 0x70, 0xb8, 0x5c, 0x2e, 0x97, 0x4b];
+
+    #[test]
+    fn every_amount() {
+	let mut s = Mb14241::new();
+	s.write_data(0xa5);
+	s.write_data(0xc3);
+	for n in 0..8 {
+	    s.write_amount(n);
+	    assert_eq!(s.read_result(), RESULTS[n as usize], "amount {n}");
+	    assert_eq!(s.read_reversed(), REVERSED[n as usize], "amount {n}");
+	}
+    }
+
+    //only the low 3 bits of the amount are wired up
+    #[test]
+    fn amount_wraps() {
+	let mut s = Mb14241::new();
+	s.write_data(0xa5);
+	s.write_data(0xc3);
+	s.write_amount(0xfb);
+	assert_eq!(s.read_result(), RESULTS[3]);
+    }
+
+    //each write pushes the oldest byte out
+    #[test]
+    fn data_shifts_through() {
+	let mut s = Mb14241::new();
+	s.write_amount(4);
+	assert_eq!(s.read_result(), 0);
+	s.write_data(0xff);
+	assert_eq!(s.read_result(), 0xf0);
+	s.write_data(0x00);
+	assert_eq!(s.read_result(), 0x0f);
+	s.write_data(0x00);
+	assert_eq!(s.read_result(), 0);
+    }
+}
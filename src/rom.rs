@@ -0,0 +1,168 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use crate::driver::{self, Driver};
+
+//one chip of a rom set and where it goes in the image
+pub struct RomFile {
+    pub name: &'static str,
+    pub offs: usize,
+    pub size: usize,
+    pub crc: Option<u32>, //None if not known
+}
+
+struct Entry {
+    name: String,
+    crc: u32,
+    data: Vec<u8>,
+}
+
+//path can be a MAME zip, a directory of the split files, or an image
+//that was already put together, which is used as is. a chip with the
+//wrong checksum is an error unless check_crc is off, for hacks
+pub fn load(path: &str, set: &[RomFile], check_crc: bool) -> Result<Vec<u8>, String> {
+    match read_set(path)? {
+	Some(entries) => assemble(path, &entries, set, check_crc),
+	None => std::fs::read(path).map_err(|e| format!("{path}: {e}")),
+    }
+}
+
+//like load, but only from a zip or directory. for parts like colour
+//proms that a concatenated cpu image can't have
+pub fn load_set(path: &str, set: &[RomFile], check_crc: bool) -> Result<Vec<u8>, String> {
+    match read_set(path)? {
+	Some(entries) => assemble(path, &entries, set, check_crc),
+	None => Err(format!("{path}: not a zip or directory")),
+    }
+}
+
+fn assemble(path: &str, entries: &[Entry], set: &[RomFile], check_crc: bool) -> Result<Vec<u8>, String> {
+    let mut image = vec![0; set.iter().map(|r| r.offs + r.size).max().unwrap_or(0)];
+    let mut errors = Vec::new();
+    for rom in set {
+	//renamed files are fine as long as the contents are right
+	let found = entries.iter().find(|e| e.name.eq_ignore_ascii_case(rom.name))
+	    .or_else(|| entries.iter().find(|e| Some(e.crc) == rom.crc && e.data.len() == rom.size));
+	let Some(e) = found else {
+	    errors.push(format!("{}: not found", rom.name));
+	    continue;
+	};
+	if e.data.len() != rom.size {
+	    errors.push(format!("{}: wrong size, expected {} bytes but found {}", rom.name, rom.size, e.data.len()));
+	    continue;
+	}
+	match rom.crc {
+	    Some(crc) if crc != e.crc && check_crc => {
+		errors.push(format!("{}: wrong checksum, expected crc {crc:08x} but found {:08x} \
+				     (check_crc false runs it anyway)", rom.name, e.crc));
+		continue;
+	    },
+	    _ => {},
+	}
+	image[rom.offs..rom.offs + rom.size].copy_from_slice(&e.data);
+    }
+
+    if errors.is_empty() {
+	Ok(image)
+    } else {
+	Err(format!("{path}: bad rom set\n{}", errors.join("\n")))
+    }
+}
+
+//which game a zip or directory holds, going by its name first and then
+//by which driver's files are all there
+pub fn detect(path: &str) -> Option<&'static Driver> {
+    let stem = Path::new(path).file_stem()?.to_string_lossy().to_lowercase();
+    if let Some(d) = driver::find(&stem) {
+	return Some(d);
+    }
+    let entries = read_set(path).ok()??;
+    driver::DRIVERS.iter().copied().find(|d| d.roms.iter().all(|rom| {
+	entries.iter().any(|e| e.name.eq_ignore_ascii_case(rom.name) || Some(e.crc) == rom.crc)
+    }))
+}
+
+//where to look for a game's roms when no path was given
+pub fn default_path(game: &str) -> String {
+    let zip = format!("{game}.zip");
+    let bin = format!("{game}.bin");
+    let found = [zip.as_str(), game, bin.as_str()].into_iter()
+	.find(|p| Path::new(p).exists());
+    String::from(found.unwrap_or(&zip))
+}
+
+//the files in a zip or directory, None for a single image
+fn read_set(path: &str) -> Result<Option<Vec<Entry>>, String> {
+    let p = Path::new(path);
+    if p.is_dir() {
+	return read_dir(p).map(Some);
+    }
+    let mut magic = [0; 4];
+    File::open(p).and_then(|mut f| f.read_exact(&mut magic)).map_err(|e| format!("{path}: {e}"))?;
+    if magic == *b"PK\x03\x04" {
+	read_zip(path).map(Some)
+    } else {
+	Ok(None)
+    }
+}
+
+fn read_zip(path: &str) -> Result<Vec<Entry>, String> {
+    let file = File::open(path).map_err(|e| format!("{path}: {e}"))?;
+    let mut zip = zip::ZipArchive::new(file).map_err(|e| format!("{path}: {e}"))?;
+    let mut entries = Vec::new();
+    for i in 0..zip.len() {
+	let mut f = zip.by_index(i).map_err(|e| format!("{path}: {e}"))?;
+	if !f.is_file() {
+	    continue;
+	}
+	let name = f.name().rsplit('/').next().unwrap_or("").to_string();
+	let mut data = Vec::new();
+	f.read_to_end(&mut data).map_err(|e| format!("{path}: {name}: {e}"))?;
+	entries.push(Entry { name, crc: crc32fast::hash(&data), data });
+    }
+    Ok(entries)
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<Entry>, String> {
+    let d = dir.display();
+    let mut entries = Vec::new();
+    for f in std::fs::read_dir(dir).map_err(|e| format!("{d}: {e}"))? {
+	let path = f.map_err(|e| format!("{d}: {e}"))?.path();
+	if !path.is_file() {
+	    continue;
+	}
+	let data = std::fs::read(&path).map_err(|e| format!("{}: {e}", path.display()))?;
+	let name = path.file_name().unwrap().to_string_lossy().into_owned();
+	entries.push(Entry { name, crc: crc32fast::hash(&data), data });
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //a two chip set in a directory of its own, the second one corrupted
+    #[test]
+    fn checksums() {
+	let dir = std::env::temp_dir().join(format!("invaders-rom-test-{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let (a, b) = ([1u8, 2, 3, 4], [5u8, 6, 7, 8]);
+	std::fs::write(dir.join("a.bin"), a).unwrap();
+	std::fs::write(dir.join("B.BIN"), [5, 6, 7, 9]).unwrap();
+	let set = [
+	    RomFile { name: "a.bin", offs: 0, size: 4, crc: Some(crc32fast::hash(&a)) },
+	    RomFile { name: "b.bin", offs: 8, size: 4, crc: Some(crc32fast::hash(&b)) },
+	];
+	let path = dir.to_str().unwrap();
+
+	let err = load(path, &set, true).unwrap_err();
+	assert!(err.contains("b.bin: wrong checksum"), "{err}");
+	let image = load(path, &set, false).unwrap();
+	assert_eq!(image, [1, 2, 3, 4, 0, 0, 0, 0, 5, 6, 7, 9]);
+
+	std::fs::write(dir.join("B.BIN"), b).unwrap();
+	assert_eq!(load(path, &set, true).unwrap(), [1, 2, 3, 4, 0, 0, 0, 0, 5, 6, 7, 8]);
+	std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,261 @@
+//Space Invaders' own variables, read out of its work ram. addresses are
+//from the well known disassembly of the midway roms, the other games on
+//the board keep theirs elsewhere. coordinates are the game's, which are
+//in pixels with the monitor on its side
+use crate::bus::InvBus;
+use std::fmt;
+
+const ALIEN_ROWS: usize = 5;
+const ALIEN_COLS: usize = 11;
+pub const ALIENS: usize = ALIEN_ROWS * ALIEN_COLS;
+
+//each player's rack, shields and ships are kept in a page of their own
+const PLAYER_PAGES: [u16; 2] = [0x2100, 0x2200];
+const SHIELDS: u16 = 0x42; //4 shields of 44 bytes each
+const SHIELD_BYTES: u16 = 44;
+const RACK_COUNT: u16 = 0xfe;
+const SHIPS: u16 = 0xff; //ships left besides the one in play
+
+const REF_ALIEN_Y: u16 = 0x2009;
+const REF_ALIEN_X: u16 = 0x200a;
+const RACK_DIRECTION: u16 = 0x200d; //0 going right, 1 going left
+const PLAYER_ALIVE: u16 = 0x2015; //ff while alive, other values as it blows up
+const PLAYER_X: u16 = 0x201b;
+const PLAYER_SHOT: u16 = 0x2025; //status, then y and x at +4 and +5
+const ALIEN_SHOTS: [u16; 3] = [0x2035, 0x2045, 0x2055]; //rolling, plunger, squiggly
+const CURRENT_PLAYER: u16 = 0x2067; //high byte of the player's page
+const NUM_ALIENS: u16 = 0x2082;
+const UFO_ACTIVE: u16 = 0x2084;
+const UFO_HIT: u16 = 0x2085;
+const TILL_UFO: u16 = 0x2091; //counts down to the next one, low byte first
+const CREDITS: u16 = 0x20eb; //BCD
+const GAME_MODE: u16 = 0x20ef; //1 while a game is being played
+pub(crate) const HIGH_SCORE: u16 = 0x20f4; //scores are BCD, low byte first
+const SCORES: [u16; 2] = [0x20f8, 0x20fc];
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Shot {
+    pub status: u8, //0 when there's no shot, what the rest mean depends on the shot
+    pub x: u8,
+    pub y: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InvadersState {
+    pub playing: bool,
+    pub credits: u8,
+    pub scores: [u32; 2],
+    pub high_score: u32,
+    pub lives: [u8; 2], //ships left besides the one in play
+    pub player: usize, //0 or 1
+    pub player_alive: bool,
+    pub player_x: u8,
+    pub aliens: [bool; ALIENS], //the current player's, bottom row first
+    pub num_aliens: u8,
+    pub rack_x: u8, //the bottom left alien, the rest are laid out from it
+    pub rack_y: u8,
+    pub rack_left: bool,
+    pub racks: u8, //how many the current player has cleared
+    pub player_shot: Shot,
+    pub alien_shots: [Shot; 3],
+    pub ufo_active: bool,
+    pub ufo_hit: bool,
+    pub till_ufo: u16,
+    //lit pixels left in each shield, from the copy the game keeps for
+    //each player. it's only brought up to date when the turn changes
+    pub shields: [[u16; 4]; 2],
+}
+
+impl InvadersState {
+    pub fn read(bus: &InvBus) -> Self {
+	let byte = |addr: u16| bus.peek(addr);
+	let word = |addr: u16| bus.peek(addr) as u16 | (bus.peek(addr + 1) as u16) << 8;
+	let score = |addr: u16| bcd(byte(addr)) + bcd(byte(addr + 1)) * 100;
+	let shot = |addr: u16, y: u16, x: u16| Shot {
+	    status: byte(addr),
+	    x: byte(addr + x),
+	    y: byte(addr + y),
+	};
+	let player = (byte(CURRENT_PLAYER) == 0x22) as usize;
+	let page = PLAYER_PAGES[player];
+	let mut aliens = [false; ALIENS];
+	for (i, a) in aliens.iter_mut().enumerate() {
+	    *a = byte(page + i as u16) != 0;
+	}
+	let shields = PLAYER_PAGES.map(|p| {
+	    let mut lit = [0; 4];
+	    for (n, l) in lit.iter_mut().enumerate() {
+		let start = p + SHIELDS + n as u16 * SHIELD_BYTES;
+		*l = (start..start + SHIELD_BYTES).map(|a| byte(a).count_ones() as u16).sum();
+	    }
+	    lit
+	});
+	InvadersState {
+	    playing: byte(GAME_MODE) == 1,
+	    credits: bcd(byte(CREDITS)) as u8,
+	    scores: SCORES.map(score),
+	    high_score: score(HIGH_SCORE),
+	    lives: PLAYER_PAGES.map(|p| byte(p + SHIPS)),
+	    player,
+	    player_alive: byte(PLAYER_ALIVE) == 0xff,
+	    player_x: byte(PLAYER_X),
+	    aliens,
+	    num_aliens: byte(NUM_ALIENS),
+	    rack_x: byte(REF_ALIEN_X),
+	    rack_y: byte(REF_ALIEN_Y),
+	    rack_left: byte(RACK_DIRECTION) != 0,
+	    racks: byte(page + RACK_COUNT),
+	    player_shot: shot(PLAYER_SHOT, 4, 5),
+	    alien_shots: ALIEN_SHOTS.map(|a| shot(a, 8, 9)),
+	    ufo_active: byte(UFO_ACTIVE) != 0,
+	    ufo_hit: byte(UFO_HIT) != 0,
+	    till_ufo: word(TILL_UFO),
+	    shields,
+	}
+    }
+
+    //where alien i is, they're 16 pixels apart each way
+    pub fn alien_pos(&self, i: usize) -> (u8, u8) {
+	let (row, col) = (i / ALIEN_COLS, i % ALIEN_COLS);
+	(self.rack_x.wrapping_add(col as u8 * 16), self.rack_y.wrapping_add(row as u8 * 16))
+    }
+}
+
+//the debugger panel
+impl fmt::Display for InvadersState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+	writeln!(f, "{} credits {}  high {:04}  1up {:04} ships {}  2up {:04} ships {}  player {}",
+		 if self.playing { "playing" } else { "attract" }, self.credits, self.high_score,
+		 self.scores[0], self.lives[0], self.scores[1], self.lives[1], self.player + 1)?;
+	writeln!(f, "cannon x {:3} {}  shot {:02x} at {:3},{:3}",
+		 self.player_x, if self.player_alive { "alive" } else { "hit  " },
+		 self.player_shot.status, self.player_shot.x, self.player_shot.y)?;
+	writeln!(f, "rack at {:3},{:3} going {}  {} aliens  {} racks cleared",
+		 self.rack_x, self.rack_y, if self.rack_left { "left " } else { "right" },
+		 self.num_aliens, self.racks)?;
+	for row in (0..ALIEN_ROWS).rev() {
+	    let cells: String = self.aliens[row * ALIEN_COLS..(row + 1) * ALIEN_COLS].iter()
+		.map(|&a| if a { 'M' } else { '.' })
+		.collect();
+	    writeln!(f, "  {cells}")?;
+	}
+	let shots: Vec<String> = ["rolling", "plunger", "squiggly"].iter().zip(self.alien_shots)
+	    .map(|(name, s)| format!("{name} {:02x} at {:3},{:3}", s.status, s.x, s.y))
+	    .collect();
+	writeln!(f, "{}", shots.join("  "))?;
+	writeln!(f, "ufo {}{}  next in {}  shields {:?}",
+		 if self.ufo_active { "active" } else { "away" }, if self.ufo_hit { ", hit" } else { "" },
+		 self.till_ufo, self.shields[self.player])
+    }
+}
+
+pub(crate) fn bcd(b: u8) -> u32 {
+    (b >> 4) as u32 * 10 + (b & 0xf) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::Bus;
+
+    //player 2 part way through their second rack
+    #[test]
+    fn read_ram_image() {
+	let mut bus = InvBus::new();
+	let mut poke = |addr: u16, data: &[u8]| {
+	    for (i, &d) in data.iter().enumerate() {
+		bus.write_byte(addr + i as u16, d);
+	    }
+	};
+	poke(GAME_MODE, &[1]);
+	poke(CREDITS, &[0x12]);
+	poke(HIGH_SCORE, &[0x50, 0x73]);
+	poke(SCORES[0], &[0x10, 0x02]);
+	poke(SCORES[1], &[0x90, 0x45]);
+	poke(CURRENT_PLAYER, &[0x22]);
+	poke(PLAYER_PAGES[0] + SHIPS, &[2]);
+	poke(PLAYER_PAGES[1] + SHIPS, &[1]);
+	poke(PLAYER_PAGES[1] + RACK_COUNT, &[1]);
+	poke(PLAYER_PAGES[1], &[1, 0, 1]);
+	poke(PLAYER_PAGES[1] + ALIENS as u16 - 1, &[1]);
+	poke(PLAYER_PAGES[0] + SHIELDS, &[0xff, 0x0f]);
+	poke(PLAYER_PAGES[1] + SHIELDS + 3 * SHIELD_BYTES, &[0x81]);
+	poke(NUM_ALIENS, &[3]);
+	poke(REF_ALIEN_Y, &[0x78, 0x20]);
+	poke(RACK_DIRECTION, &[1]);
+	poke(PLAYER_ALIVE, &[0xff]);
+	poke(PLAYER_X, &[0x40]);
+	poke(PLAYER_SHOT, &[1, 0, 0, 0, 0x60, 0x44]);
+	poke(ALIEN_SHOTS[2], &[2, 0, 0, 0, 0, 0, 0, 0, 0x90, 0x30]);
+	poke(UFO_ACTIVE, &[1, 0]);
+	poke(TILL_UFO, &[0x34, 0x02]);
+
+	let s = InvadersState::read(&bus);
+	assert!(s.playing);
+	assert_eq!(s.credits, 12);
+	assert_eq!(s.high_score, 7350);
+	assert_eq!(s.scores, [210, 4590]);
+	assert_eq!(s.player, 1);
+	assert_eq!(s.lives, [2, 1]);
+	assert_eq!(s.racks, 1);
+	let alive: Vec<usize> = (0..ALIENS).filter(|&i| s.aliens[i]).collect();
+	assert_eq!(alive, [0, 2, ALIENS - 1]);
+	assert_eq!(s.num_aliens, 3);
+	assert_eq!(s.shields, [[12, 0, 0, 0], [0, 0, 0, 2]]);
+	assert_eq!((s.rack_x, s.rack_y, s.rack_left), (0x20, 0x78, true));
+	assert_eq!(s.alien_pos(ALIENS - 1), (0x20 + 10 * 16, 0x78 + 4 * 16));
+	assert!(s.player_alive);
+	assert_eq!(s.player_x, 0x40);
+	assert_eq!(s.player_shot, Shot { status: 1, x: 0x44, y: 0x60 });
+	assert_eq!(s.alien_shots[0], Shot::default());
+	assert_eq!(s.alien_shots[2], Shot { status: 2, x: 0x30, y: 0x90 });
+	assert!(s.ufo_active && !s.ufo_hit);
+	assert_eq!(s.till_ufo, 0x234);
+    }
+
+    //a board with no roms and its ram still clear, so only that zeroes
+    //decode as no game and nothing scored
+    #[test]
+    fn cleared_ram() {
+	let s = InvadersState::read(&InvBus::new());
+	assert!(!s.playing);
+	assert_eq!((s.credits, s.high_score, s.player), (0, 0, 0));
+	assert!(s.aliens.iter().all(|&a| !a));
+    }
+
+    //player 1's game as the game would leave ram along the way, to check
+    //lives, score and wave follow it
+    #[test]
+    fn progress() {
+	let mut bus = InvBus::new();
+	let mut poke = |addr: u16, data: &[u8]| {
+	    for (i, &d) in data.iter().enumerate() {
+		bus.write_byte(addr + i as u16, d);
+	    }
+	    let s = InvadersState::read(&bus);
+	    (s.playing, s.scores[0], s.lives[0], s.racks, s.num_aliens)
+	};
+	//start: a full rack and two ships in reserve
+	poke(CURRENT_PLAYER, &[0x21]);
+	poke(PLAYER_PAGES[0], &[1; ALIENS]);
+	poke(NUM_ALIENS, &[ALIENS as u8]);
+	poke(PLAYER_PAGES[0] + SHIPS, &[2]);
+	assert_eq!(poke(GAME_MODE, &[1]), (true, 0, 2, 0, 55));
+	//a 30 point squid, then the ufo for 150 crossing a hundred
+	poke(PLAYER_PAGES[0] + ALIENS as u16 - 1, &[0]);
+	assert_eq!(poke(NUM_ALIENS, &[54]), (true, 0, 2, 0, 54));
+	assert_eq!(poke(SCORES[0], &[0x30]), (true, 30, 2, 0, 54));
+	assert_eq!(poke(SCORES[0], &[0x80, 0x01]), (true, 180, 2, 0, 54));
+	//shot down
+	assert_eq!(poke(PLAYER_PAGES[0] + SHIPS, &[1]), (true, 180, 1, 0, 54));
+	//rack cleared, the next one comes in and the count goes up
+	poke(PLAYER_PAGES[0], &[0; ALIENS]);
+	poke(NUM_ALIENS, &[0]);
+	poke(PLAYER_PAGES[0] + RACK_COUNT, &[1]);
+	poke(PLAYER_PAGES[0], &[1; ALIENS]);
+	assert_eq!(poke(NUM_ALIENS, &[55]), (true, 180, 1, 1, 55));
+	//last ship gone, the score stays up after the game ends
+	poke(PLAYER_PAGES[0] + SHIPS, &[0]);
+	assert_eq!(poke(GAME_MODE, &[0]), (false, 180, 0, 1, 55));
+    }
+}
@@ -0,0 +1,9 @@
+//shared by the module tests
+
+//xorshift, the same bytes every run for a given seed
+pub(crate) fn noise(seed: &mut u32) -> u8 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 17;
+    *seed ^= *seed << 5;
+    *seed as u8
+}
@@ -0,0 +1,315 @@
+pub const WIDTH: usize = 224;
+pub const HEIGHT: usize = 256;
+
+const WHITE: u32 = 0xffffff;
+const RED: u32 = 0xff2020;
+const GREEN: u32 = 0x20ff20;
+
+//a rectangle of the cellophane overlay, in rotated game coordinates
+//(0, 0 is the top left of the screen as the player sees it)
+#[derive(Clone, Copy, Debug)]
+pub struct Band {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+    pub color: u32,
+}
+
+impl Band {
+    pub const fn rows(top: usize, bottom: usize, color: u32) -> Self {
+	Band { top, bottom, left: 0, right: WIDTH, color }
+    }
+}
+
+//the midway cabinet's cellophane, red over the scores and the ufo's path
+//and green over the bottom
+const MIDWAY_BANDS: [Band; 3] = [
+    Band::rows(0, 64, RED), //scores and ufo
+    Band::rows(184, 240, GREEN), //shields and player
+    Band { top: 240, bottom: HEIGHT, left: 16, right: 134, color: GREEN }, //reserve lives
+];
+
+//rough approximation of the taito colour cabinet, which coloured each alien row
+const TAITO_BANDS: [Band; 7] = [
+    Band::rows(0, 32, 0x20ffff), //score
+    Band::rows(32, 64, 0xff20ff), //ufo
+    Band::rows(64, 96, 0xffff20),
+    Band::rows(96, 128, 0x20ff20),
+    Band::rows(128, 184, 0x20ffff),
+    Band::rows(184, 240, 0xff2020), //shields and player
+    Band::rows(240, HEIGHT, 0xff20ff),
+];
+
+#[derive(Clone, Debug)]
+pub enum Overlay {
+    Bw,
+    Midway,
+    Taito,
+    Custom(Vec<Band>),
+}
+
+impl Overlay {
+    pub fn from_name(name: &str) -> Option<Self> {
+	match name {
+	    "bw" => Some(Overlay::Bw),
+	    "midway" => Some(Overlay::Midway),
+	    "taito" => Some(Overlay::Taito),
+	    "custom" => Some(Overlay::Custom(Vec::new())),
+	    _ => None,
+	}
+    }
+
+    pub fn bands(&self) -> &[Band] {
+	match self {
+	    Overlay::Bw => &[],
+	    Overlay::Midway => &MIDWAY_BANDS,
+	    Overlay::Taito => &TAITO_BANDS,
+	    Overlay::Custom(b) => b,
+	}
+    }
+
+    //colour of a lit pixel at every screen position, later bands win
+    pub fn color_map(&self) -> Vec<u32> {
+	let mut map = vec![WHITE; WIDTH * HEIGHT];
+	for band in self.bands() {
+	    for y in band.top.min(HEIGHT)..band.bottom.min(HEIGHT) {
+		for x in band.left.min(WIDTH)..band.right.min(WIDTH) {
+		    map[y * WIDTH + x] = band.color;
+		}
+	    }
+	}
+	map
+    }
+}
+
+pub fn scale_rgb(c: u32, k: f32) -> u32 {
+    let ch = |s: u32| (((c >> s) & 0xff) as f32 * k).clamp(0.0, 255.0) as u32;
+    (ch(16) << 16) | (ch(8) << 8) | ch(0)
+}
+
+//per channel, saturating
+pub fn add_rgb(a: u32, b: u32) -> u32 {
+    let ch = |s: u32| (((a >> s) & 0xff) + ((b >> s) & 0xff)).min(0xff);
+    (ch(16) << 16) | (ch(8) << 8) | ch(0)
+}
+
+pub fn max_rgb(a: u32, b: u32) -> u32 {
+    let ch = |s: u32| ((a >> s) & 0xff).max((b >> s) & 0xff);
+    (ch(16) << 16) | (ch(8) << 8) | ch(0)
+}
+
+//where to put the picture in a window of out_w x out_h, letterboxed to keep
+//the given width/height aspect. integer scaling only uses whole multiples of
+//the vertical resolution so every line is the same height
+pub fn fit(out_w: u32, out_h: u32, aspect: f32, integer: bool) -> (i32, i32, u32, u32) {
+    let mut h = (out_h as f32).min(out_w as f32 / aspect);
+    if integer && h >= HEIGHT as f32 {
+	h = (h as u32 / HEIGHT as u32 * HEIGHT as u32) as f32;
+    }
+    let w = (h * aspect).round() as u32;
+    let h = h.round() as u32;
+    ((out_w as i32 - w as i32) / 2, (out_h as i32 - h as i32) / 2, w, h)
+}
+
+//per pixel colours from the colour of each 8x8 cell, as from
+//InvBus::take_cell_colors. the cells count from the start of work ram,
+//so the first 4 rows of them are never on screen
+pub fn cell_color_map(cells: &[u8]) -> Vec<u32> {
+    let mut map = vec![0; WIDTH * HEIGHT];
+    for offs in 0..WIDTH * 32 {
+	let cell = (((offs + 0x400) >> 8) << 5) | (offs & 0x1f);
+	let c = cells[cell];
+	//3 bit colour, red in bit 0, blue in bit 1, green in bit 2
+	let rgb = ((c & 1) as u32 * 0xff0000) | ((c >> 2 & 1) as u32 * 0x00ff00) | ((c >> 1 & 1) as u32 * 0x0000ff);
+	let bottom = HEIGHT - 1 - (offs % 32) * 8;
+	for b in 0..8 {
+	    map[(bottom - b) * WIDTH + offs / 32] = rgb;
+	}
+    }
+    map
+}
+
+//each vram byte is 8 pixels stacked vertically after rotation,
+//bit 0 lowest. entry b is all ones if bit b is set
+const BYTE_LUT: [[u32; 8]; 256] = {
+    let mut lut = [[0; 8]; 256];
+    let mut i = 0;
+    while i < 256 {
+	let mut b = 0;
+	while b < 8 {
+	    if (i >> b) & 1 != 0 {
+		lut[i][b] = !0;
+	    }
+	    b += 1;
+	}
+	i += 1;
+    }
+    lut
+};
+
+//the rotated picture, kept between frames so only the parts of vram
+//that were written need converting again
+pub struct Screen {
+    colors: Vec<u32>,
+    redraw: bool,
+    pub px: Vec<u32>,
+}
+
+impl Screen {
+    pub fn new(colors: Vec<u32>) -> Self {
+	Screen {
+	    colors,
+	    redraw: false,
+	    px: vec![0; WIDTH * HEIGHT],
+	}
+    }
+
+    //everything gets redrawn in the new colours on the next update
+    pub fn set_colors(&mut self, colors: Vec<u32>) {
+	self.colors = colors;
+	self.redraw = true;
+    }
+
+    //dirty has one bit per vram byte, as from InvBus::take_display_dirty
+    pub fn update(&mut self, vram: &[u8], dirty: &[u64]) {
+	if self.redraw {
+	    self.redraw = false;
+	    for (offs, byte) in vram.iter().enumerate() {
+		self.draw_byte(offs, *byte);
+	    }
+	    return;
+	}
+	let count: u32 = dirty.iter().map(|w| w.count_ones()).sum();
+	if count as usize > vram.len() / 8 {
+	    //lots to do, go across the screen a strip of 8 rows at a time
+	    //so the writes stay in the same few cache lines
+	    for col in 0..32 {
+		for row in 0..WIDTH {
+		    let offs = row * 32 + col;
+		    if (dirty[offs / 64] >> (offs % 64)) & 1 != 0 {
+			self.draw_byte(offs, vram[offs]);
+		    }
+		}
+	    }
+	    return;
+	}
+
+	for (w, bits) in dirty.iter().enumerate() {
+	    let mut bits = *bits;
+	    while bits != 0 {
+		let offs = w * 64 + bits.trailing_zeros() as usize;
+		bits &= bits - 1;
+		self.draw_byte(offs, vram[offs]);
+	    }
+	}
+    }
+
+    fn draw_byte(&mut self, offs: usize, byte: u8) {
+	//32 bytes per vram row, each row is one column on screen
+	let x = offs / 32;
+	let base = (HEIGHT - 8 - (offs % 32) * 8) * WIDTH + x;
+	let px = &mut self.px[base..base + 7 * WIDTH + 1];
+	let colors = &self.colors[base..base + 7 * WIDTH + 1];
+	let mask = &BYTE_LUT[byte as usize];
+	for k in 0..8 {
+	    px[k * WIDTH] = colors[k * WIDTH] & mask[7 - k];
+	}
+    }
+}
+
+//rotate the 1bpp framebuffer into 0x00RRGGBB pixels, WIDTH * HEIGHT of them.
+//converts everything every time, Screen only redoes what changed
+pub fn render(vram: &[u8], colors: &[u32], frame: &mut [u32]) {
+    for x in (0..256).step_by(8) {
+	for y in 0..WIDTH {
+	    let offs: usize = (y * (256 / 8)) + (x / 8);
+	    for b in (0..8).rev() {
+		let bufy: usize = 256 - 1 - (x + b);
+		let i = bufy * WIDTH + y;
+		frame[i] = match (vram[offs] >> b) & 1 {
+		    0 => 0,
+		    _ => colors[i],
+		};
+	    }
+	}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::noise;
+
+    fn rendered(vram: &[u8], colors: &[u32]) -> Vec<u32> {
+	let mut frame = vec![0; WIDTH * HEIGHT];
+	render(vram, colors, &mut frame);
+	frame
+    }
+
+    //screen against the plain rotation after a full redraw, a few bytes
+    //changed (the sparse path) and a lot changed (the strip path)
+    #[test]
+    fn screen_matches_render() {
+	let colors = Overlay::Midway.color_map();
+	let mut seed = 1;
+	let mut vram: Vec<u8> = (0..0x1c00).map(|_| noise(&mut seed)).collect();
+	let mut screen = Screen::new(colors.clone());
+	screen.update(&vram, &[!0; 0x1c00 / 64]);
+	assert!(screen.px == rendered(&vram, &colors));
+
+	for changes in [20, 3000] {
+	    let mut dirty = [0u64; 0x1c00 / 64];
+	    for _ in 0..changes {
+		let offs = ((noise(&mut seed) as usize) << 8 | noise(&mut seed) as usize) % 0x1bff;
+		vram[offs] = noise(&mut seed);
+		dirty[offs / 64] |= 1 << (offs % 64);
+	    }
+	    //and a byte that wasn't marked must be left alone
+	    let stale = rendered(&vram, &colors);
+	    vram[0x1bff] ^= 0xff;
+	    screen.update(&vram, &dirty);
+	    vram[0x1bff] ^= 0xff;
+	    assert!(screen.px == stale, "{changes} changes");
+	}
+
+	let colors = Overlay::Taito.color_map();
+	screen.set_colors(colors.clone());
+	screen.update(&vram, &[0; 0x1c00 / 64]);
+	assert!(screen.px == rendered(&vram, &colors));
+    }
+
+    //red from the scores through the ufo, white over the aliens, green
+    //below apart from the credits at the bottom right
+    #[test]
+    fn midway_overlay() {
+	let map = Overlay::Midway.color_map();
+	let at = |x: usize, y: usize| map[y * WIDTH + x];
+	for (x, y, c) in [(0, 0, RED), (200, 20, RED), (100, 63, RED), (100, 64, WHITE), (0, 183, WHITE),
+			  (0, 184, GREEN), (223, 239, GREEN), (16, 250, GREEN), (8, 250, WHITE), (200, 250, WHITE)] {
+	    assert_eq!(at(x, y), c, "at {x},{y}");
+	}
+    }
+
+    //cell 128 is the bottom left corner of the screen, 191 is at the top
+    //one cell in. red is bit 0, blue bit 1 and green bit 2
+    #[test]
+    fn cell_colors() {
+	let mut cells = vec![0; 0x400];
+	cells[128] = 1;
+	cells[191] = 6;
+	let map = cell_color_map(&cells);
+	for y in 0..HEIGHT {
+	    for x in 0..WIDTH {
+		let want = match (x / 8, y / 8) {
+		    (0, 31) => 0xff0000,
+		    (1, 0) => 0x00ffff,
+		    _ => 0,
+		};
+		assert_eq!(map[y * WIDTH + x], want, "at {x},{y}");
+	    }
+	}
+	//lit pixels take the colour of their cell
+	assert!(rendered(&[0xff; 0x1c00], &map) == map);
+    }
+}